@@ -1,9 +1,11 @@
 use std::rc::Rc;
+use std::cmp::{min, max};
 use std::cell::RefCell;
 use std::any::Any;
-use sprite::{Sprite, SpriteAnimation};
+use sprite::{Sprite, SpriteAnimation, AnimationBox, AnimationBoxKind};
 use game::GameState;
 use map::BlendMode;
+use trigger::TriggerZoneRef;
 
 pub struct SpriteWithOffset {
 	pub sprite: Rc<Sprite>,
@@ -12,7 +14,40 @@ pub struct SpriteWithOffset {
 	pub x_offset: isize,
 	pub y_offset: isize,
 	pub blend_mode: BlendMode,
-	pub alpha: u8
+	pub alpha: u8,
+	pub priority: i32,
+	pub placement: SpriteLayerPlacement,
+	// Playback speed in 1/256ths of the animation's own rate
+	pub playback_speed: usize,
+	pub playback_fraction: usize,
+	pub playback_mode: AnimationPlaybackMode,
+	pub queued_animation: Option<Rc<SpriteAnimation>>,
+	pub frame_event_pending: bool
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnimationPlaybackMode {
+	Forward,
+	Reverse,
+	// Plays forward then backward. Non-looping animations finish when they return to the first frame.
+	PingPong
+}
+
+// Position of a sprite in the map layer stack. Sprites render with the actors on the map's main layer
+// by default, but can be placed above or below any other layer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpriteLayerPlacement {
+	MainLayer,
+	AboveLayer(usize),
+	BelowLayer(usize)
+}
+
+// Order of actor sprites within a layer. Sprites are sorted by actor priority, then by the bottom edge
+// of the actor when y sorting, then by sprite priority. Ties keep the order of the actor list.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DepthSortMode {
+	Priority,
+	YPosition
 }
 
 #[derive(Debug, Clone)]
@@ -23,6 +58,15 @@ pub struct BoundingRect {
 	pub height: isize
 }
 
+// Pair of animation boxes from two actors that overlap, `kind` being the box of the actor that made the
+// query. A hitbox overlapping a hurtbox of another actor is reported as `Hitbox` and `Hurtbox`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoxOverlap {
+	pub kind: AnimationBoxKind,
+	pub other_kind: AnimationBoxKind,
+	pub channel: u32
+}
+
 pub struct ActorInfo {
 	pub x: isize,
 	pub y: isize,
@@ -33,9 +77,22 @@ pub struct ActorInfo {
 	pub collision_bounds: Option<BoundingRect>,
 	pub collision_channel: u32,
 	pub blocking_collision: bool,
+	// Bits for the collision layers the actor is on, and the layers it interacts with. Two actors only
+	// interact if each one's mask includes a layer of the other.
+	pub collision_layer: u32,
+	pub collision_mask: u32,
+	// Triggers are reported by collision checks, but never block or are blocked by other actors
+	pub trigger: bool,
+	// Platforms move before other actors each frame, carrying the actors standing on them and pushing
+	// the actors they move into. They are only blocked by the map and by other platforms.
+	pub platform: bool,
+	pub physics: Option<PhysicsBody>,
+	// Surfaces the actor was touching after its last move
+	pub contacts: ContactFlags,
 	pub sprites: Vec<SpriteWithOffset>,
 	pub destroyed: bool,
 	pub health: i32,
+	pub priority: i32
 }
 
 pub type ActorRef = Rc<RefCell<Box<Actor>>>;
@@ -45,12 +102,210 @@ pub trait ActorAsAny {
 	fn as_any_mut(&mut self) -> &mut Any;
 }
 
+impl SpriteWithOffset {
+	pub fn set_animation(&mut self, animation: Rc<SpriteAnimation>) {
+		self.animation = animation;
+		self.animation_frame = 0;
+		self.playback_fraction = 0;
+		self.queued_animation = None;
+		self.frame_event_pending = true;
+	}
+
+	// Length of one pass through the animation in the current playback mode. Ping-pong turns around
+	// without repeating the end frames.
+	fn playback_length(&self) -> usize {
+		let total_length = self.animation.animation.total_length;
+		if total_length == 0 {
+			return 0;
+		}
+		match self.playback_mode {
+			AnimationPlaybackMode::PingPong => {
+				if self.animation.looping {
+					((total_length * 2) - 2).max(1)
+				} else {
+					(total_length * 2) - 1
+				}
+			},
+			_ => total_length
+		}
+	}
+
+	// Time within the animation to display, after applying the playback mode
+	pub fn animation_time(&self) -> usize {
+		let total_length = self.animation.animation.total_length;
+		let length = self.playback_length();
+		if length == 0 {
+			return 0;
+		}
+		let t = if self.animation.looping {
+			self.animation_frame % length
+		} else {
+			min(self.animation_frame, length - 1)
+		};
+		match self.playback_mode {
+			AnimationPlaybackMode::Forward => t,
+			AnimationPlaybackMode::Reverse => total_length - 1 - t,
+			AnimationPlaybackMode::PingPong => {
+				if t < total_length {
+					t
+				} else {
+					((total_length * 2) - 2) - t
+				}
+			}
+		}
+	}
+
+	pub fn current_frame(&self) -> usize {
+		self.animation.frame_for_time(self.animation_time())
+	}
+
+	pub fn is_finished(&self) -> bool {
+		match self.playback_mode {
+			AnimationPlaybackMode::Forward => self.animation.is_finished_at(self.animation_frame),
+			_ => !self.animation.looping && (self.animation_frame >= self.playback_length())
+		}
+	}
+
+	// Number of game frames until a non-looping animation finishes, or None if it never does
+	pub fn remaining_time(&self) -> Option<usize> {
+		if self.animation.looping || (self.playback_speed == 0) {
+			return None;
+		}
+		let remaining = self.playback_length().saturating_sub(self.animation_frame);
+		Some(((remaining << 8).saturating_sub(self.playback_fraction)).div_ceil(self.playback_speed))
+	}
+
+	// Moves playback to the point where the given frame is entered in the current playback mode
+	pub fn seek(&mut self, frame: usize) {
+		if frame >= self.animation.frames {
+			return;
+		}
+		let frame_lengths = &self.animation.animation.frame_lengths;
+		let start: usize = frame_lengths[..frame].iter().sum();
+		self.animation_frame = match self.playback_mode {
+			AnimationPlaybackMode::Reverse => self.animation.animation.total_length - (start + frame_lengths[frame]),
+			_ => start
+		};
+		self.playback_fraction = 0;
+	}
+
+	// Advances playback by one game frame. Returns the names of events on frames that were entered, and
+	// the name of the animation if it finished. A queued animation is started when the current one
+	// finishes.
+	pub fn advance(&mut self) -> (Vec<String>, Option<String>) {
+		let mut events = Vec::new();
+		let mut finished = None;
+
+		if self.frame_event_pending {
+			// Animation was just started, the first frame has not been announced yet
+			self.frame_event_pending = false;
+			for event in self.animation.events_for_frame(self.current_frame()) {
+				events.push(event.name.clone());
+			}
+		}
+
+		self.playback_fraction += self.playback_speed;
+		let steps = self.playback_fraction >> 8;
+		self.playback_fraction &= 0xff;
+
+		for _ in 0..steps {
+			if self.is_finished() {
+				break;
+			}
+
+			let prev_frame = self.current_frame();
+			self.animation_frame += 1;
+			if self.is_finished() {
+				finished = Some(self.animation.name.clone());
+				if let Some(queued) = self.queued_animation.take() {
+					self.set_animation(queued);
+				}
+				break;
+			}
+
+			// Frames are entered when the displayed frame changes, or when a loop restarts on the same frame
+			let entered = match self.playback_mode {
+				AnimationPlaybackMode::Forward => self.animation.frame_started_at(self.animation_frame),
+				_ => {
+					let frame = self.current_frame();
+					let restarted = (self.playback_mode == AnimationPlaybackMode::Reverse) &&
+						self.animation_frame.is_multiple_of(self.playback_length());
+					if (frame != prev_frame) || restarted {
+						Some(frame)
+					} else {
+						None
+					}
+				}
+			};
+			if let Some(frame) = entered {
+				for event in self.animation.events_for_frame(frame) {
+					events.push(event.name.clone());
+				}
+			}
+		}
+
+		(events, finished)
+	}
+}
+
 impl BoundingRect {
 	pub fn is_colliding(&self, other: &BoundingRect) -> bool {
 		(other.x < (self.x + self.width)) && (self.x < (other.x + other.width)) &&
 			(other.y < (self.y + self.height)) && (self.y < (other.y + other.height))
 	}
 
+	// Smallest rectangle containing both rectangles
+	pub fn union(&self, other: &BoundingRect) -> BoundingRect {
+		let x = min(self.x, other.x);
+		let y = min(self.y, other.y);
+		BoundingRect {
+			x, y,
+			width: max(self.x + self.width, other.x + other.width) - x,
+			height: max(self.y + self.height, other.y + other.height) - y
+		}
+	}
+
+	// Area covered by the rectangle while moving to a new position
+	pub fn sweep_area(&self, final_x: isize, final_y: isize) -> BoundingRect {
+		BoundingRect {
+			x: min(self.x, final_x),
+			y: min(self.y, final_y),
+			width: self.width + (final_x - self.x).abs(),
+			height: self.height + (final_y - self.y).abs()
+		}
+	}
+
+	// Fraction of the way along the line from (`x0`, `y0`) to (`x1`, `y1`) where it first touches the
+	// rectangle, if it does
+	pub fn ray_intersection(&self, x0: isize, y0: isize, x1: isize, y1: isize) -> Option<f32> {
+		if (self.width <= 0) || (self.height <= 0) {
+			return None;
+		}
+
+		let mut t_min = 0.0f32;
+		let mut t_max = 1.0f32;
+		let axes = [
+			(x0 as f32, (x1 - x0) as f32, self.x as f32, (self.x + self.width - 1) as f32),
+			(y0 as f32, (y1 - y0) as f32, self.y as f32, (self.y + self.height - 1) as f32)
+		];
+		for &(start, delta, low, high) in &axes {
+			if delta == 0.0 {
+				if (start < low) || (start > high) {
+					return None;
+				}
+			} else {
+				let t0 = (low - start) / delta;
+				let t1 = (high - start) / delta;
+				t_min = t_min.max(t0.min(t1));
+				t_max = t_max.min(t0.max(t1));
+				if t_min > t_max {
+					return None;
+				}
+			}
+		}
+		Some(t_min)
+	}
+
 	pub fn sweep_collision_x(&self, rect: &BoundingRect, final_x: isize) -> Option<isize> {
 		if (self.y >= (rect.y + rect.height)) || (rect.y >= (self.y + self.height)) {
 			// Not colliding on y axis
@@ -110,6 +365,82 @@ pub enum MovementCollision {
 	CollidedWithActor(ActorRef)
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ContactFlags {
+	pub grounded: bool,
+	pub ceiling: bool,
+	pub wall_left: bool,
+	pub wall_right: bool
+}
+
+impl ContactFlags {
+	fn set_wall(&mut self, direction_x: isize) {
+		if direction_x < 0 {
+			self.wall_left = true;
+		} else if direction_x > 0 {
+			self.wall_right = true;
+		}
+	}
+
+	fn set_floor_or_ceiling(&mut self, direction_y: isize) {
+		if direction_y > 0 {
+			self.grounded = true;
+		} else if direction_y < 0 {
+			self.ceiling = true;
+		}
+	}
+}
+
+pub struct MovementResult {
+	pub collision: MovementCollision,
+	pub contacts: ContactFlags
+}
+
+// Forces applied to an actor's velocity by `apply_move`. Speeds and accelerations are in the same
+// 1/256 pixel per frame units as velocity. Friction applies while on the ground and drag while in
+// the air, both slowing horizontal movement by a fixed amount per frame. Restitution is the fraction
+// of speed kept when bouncing off of a surface, in 1/256ths.
+#[derive(Debug, Clone)]
+pub struct PhysicsBody {
+	pub gravity: isize,
+	pub max_fall_speed: isize,
+	pub ground_friction: isize,
+	pub air_drag: isize,
+	pub restitution: isize
+}
+
+impl PhysicsBody {
+	pub fn new(gravity: isize, max_fall_speed: isize) -> PhysicsBody {
+		PhysicsBody {
+			gravity,
+			max_fall_speed,
+			ground_friction: 0,
+			air_drag: 0,
+			restitution: 0
+		}
+	}
+
+	// Moves a speed toward zero without crossing it
+	fn slow_down(velocity: isize, amount: isize) -> isize {
+		if velocity > 0 {
+			max(velocity - amount, 0)
+		} else {
+			min(velocity + amount, 0)
+		}
+	}
+
+	// Speed after bouncing off of a surface. Bounces too slow to overcome a frame of gravity are
+	// stopped so that bodies come to rest.
+	fn bounce(&self, velocity: isize) -> isize {
+		let bounced = -(velocity * self.restitution) / 0x100;
+		if bounced.abs() <= self.gravity {
+			0
+		} else {
+			bounced
+		}
+	}
+}
+
 pub trait Actor: ActorAsAny {
 	fn actor_info(&self) -> &ActorInfo;
 	fn actor_info_mut(&mut self) -> &mut ActorInfo;
@@ -125,8 +456,16 @@ pub trait Actor: ActorAsAny {
 		self.actor_info().destroyed
 	}
 
-	fn move_with_collision(&mut self, game_state: &GameState) -> MovementCollision {
+	fn move_with_collision(&mut self, game_state: &GameState) -> MovementResult {
 		let actor_info = self.actor_info_mut();
+		let direction_x = actor_info.velocity_x.signum();
+		let direction_y = actor_info.velocity_y.signum();
+		let mut contacts = ContactFlags::default();
+		let prev_bounds = actor_info.world_collision_bounds();
+		let riders = match &prev_bounds {
+			Some(bounds) if actor_info.platform => actor_info.find_riders(bounds, game_state),
+			_ => Vec::new()
+		};
 		let mut full_x = (actor_info.x << 8) + actor_info.subpixel_x as isize;
 		let mut full_y = (actor_info.y << 8) + actor_info.subpixel_y as isize;
 		full_x += actor_info.velocity_x;
@@ -153,18 +492,26 @@ pub trait Actor: ActorAsAny {
 				height: collision_height
 			};
 
-			if let Some(map) = &game_state.map {
+			if let Some(map) = &*game_state.map.borrow() {
+				// Actors standing on the ground follow slopes down instead of falling off of them
+				let on_ground = map.sweep_collision_y(&bounds, bounds.y + 1, actor_info.collision_channel) == Some(bounds.y);
+
 				if let Some(revised_x) = map.sweep_collision_x(&bounds, new_x + collision_x_offset, actor_info.collision_channel) {
 					new_x = revised_x - collision_x_offset;
 					full_x = new_x << 8;
 					actor_info.velocity_x = 0;
 					collision_result = MovementCollision::CollidedWithWorld;
+					contacts.set_wall(direction_x);
 				}
 
-				for actor_ref in &game_state.actors {
+				let sweep_area = bounds.sweep_area(new_x + collision_x_offset, bounds.y);
+				for actor_ref in &game_state.actors_in_rect(&sweep_area, None) {
 					if let Ok(other_actor) = actor_ref.try_borrow() {
 						let other_actor_info = other_actor.actor_info();
-						if !other_actor_info.blocking_collision {
+						if !other_actor_info.blocking_collision || !actor_info.is_blocked_by(other_actor_info) {
+							continue;
+						}
+						if actor_info.platform && !other_actor_info.platform {
 							continue;
 						}
 						if let Some(other_bounds) = &other_actor_info.collision_bounds {
@@ -185,6 +532,7 @@ pub trait Actor: ActorAsAny {
 								full_x = new_x << 8;
 								actor_info.velocity_x = 0;
 								collision_result = MovementCollision::CollidedWithActor(actor_ref.clone());
+								contacts.set_wall(direction_x);
 							}
 						}
 					}
@@ -192,17 +540,35 @@ pub trait Actor: ActorAsAny {
 
 				bounds.x = new_x + collision_x_offset;
 
+				// Walk up slopes the actor has moved into, and stay on slopes while walking down them
+				if actor_info.velocity_y >= 0 {
+					let max_drop = if on_ground { (new_x - actor_info.x).abs() + 1 } else { 0 };
+					if let Some(surface) = map.slope_surface(&bounds, max_drop, actor_info.collision_channel) {
+						new_y = surface - collision_height - collision_y_offset;
+						full_y = new_y << 8;
+						bounds.y = new_y + collision_y_offset;
+						actor_info.velocity_y = 0;
+						collision_result = MovementCollision::CollidedWithWorld;
+						contacts.grounded = true;
+					}
+				}
+
 				if let Some(revised_y) = map.sweep_collision_y(&bounds, new_y + collision_y_offset, actor_info.collision_channel) {
 					new_y = revised_y - collision_y_offset;
 					full_y = new_y << 8;
 					actor_info.velocity_y = 0;
 					collision_result = MovementCollision::CollidedWithWorld;
+					contacts.set_floor_or_ceiling(direction_y);
 				}
 
-				for actor_ref in &game_state.actors {
+				let sweep_area = bounds.sweep_area(bounds.x, new_y + collision_y_offset);
+				for actor_ref in &game_state.actors_in_rect(&sweep_area, None) {
 					if let Ok(other_actor) = actor_ref.try_borrow() {
 						let other_actor_info = other_actor.actor_info();
-						if !other_actor_info.blocking_collision {
+						if !other_actor_info.blocking_collision || !actor_info.is_blocked_by(other_actor_info) {
+							continue;
+						}
+						if actor_info.platform && !other_actor_info.platform {
 							continue;
 						}
 						if let Some(other_bounds) = &other_actor_info.collision_bounds {
@@ -223,10 +589,17 @@ pub trait Actor: ActorAsAny {
 								full_y = new_y << 8;
 								actor_info.velocity_y = 0;
 								collision_result = MovementCollision::CollidedWithActor(actor_ref.clone());
+								contacts.set_floor_or_ceiling(direction_y);
 							}
 						}
 					}
 				}
+
+				// Actors resting on the map are grounded even when not moving into it
+				bounds.y = new_y + collision_y_offset;
+				if map.sweep_collision_y(&bounds, bounds.y + 1, actor_info.collision_channel) == Some(bounds.y) {
+					contacts.grounded = true;
+				}
 			}
 		}
 
@@ -235,7 +608,16 @@ pub trait Actor: ActorAsAny {
 		actor_info.subpixel_x = (full_x & 0xff) as u8;
 		actor_info.subpixel_y = (full_y & 0xff) as u8;
 
-		collision_result
+		if let Some(prev_bounds) = &prev_bounds {
+			if actor_info.platform {
+				actor_info.move_riders(prev_bounds, &riders, game_state);
+			}
+		}
+
+		MovementResult {
+			collision: collision_result,
+			contacts
+		}
 	}
 
 	fn check_for_actor_collision(&mut self, game_state: &GameState) -> Vec<ActorRef> {
@@ -255,9 +637,12 @@ pub trait Actor: ActorAsAny {
 				height: collision_height
 			};
 
-			for actor_ref in &game_state.actors {
+			for actor_ref in &game_state.actors_in_rect(&bounds, None) {
 				if let Ok(other_actor) = actor_ref.try_borrow() {
 					let other_actor_info = other_actor.actor_info();
+					if !actor_info.can_collide_with(other_actor_info) {
+						continue;
+					}
 					if let Some(other_bounds) = &other_actor_info.collision_bounds {
 						let collision_x_offset = other_bounds.x;
 						let collision_y_offset = other_bounds.y;
@@ -282,13 +667,54 @@ pub trait Actor: ActorAsAny {
 		collided_actors
 	}
 
+	// Finds actors whose animation boxes overlap this actor's. Candidates come from the collision
+	// broadphase, so only actors with collision bounds near this actor's boxes are checked.
+	fn check_for_box_overlaps(&mut self, game_state: &GameState) -> Vec<(ActorRef, Vec<BoxOverlap>)> {
+		let actor_info = self.actor_info();
+		let mut overlaps = Vec::new();
+
+		// Area covered by all of this actor's boxes
+		let mut area: Option<BoundingRect> = None;
+		for animation_box in actor_info.animation_boxes() {
+			area = Some(match area {
+				Some(area) => area.union(&animation_box.bounds),
+				None => animation_box.bounds
+			});
+		}
+		let area = match area {
+			Some(area) => area,
+			None => return overlaps
+		};
+
+		for actor_ref in &game_state.actors_in_rect(&area, None) {
+			if let Ok(other_actor) = actor_ref.try_borrow() {
+				let other_actor_info = other_actor.actor_info();
+				if !actor_info.can_collide_with(other_actor_info) {
+					continue;
+				}
+				let actor_overlaps = actor_info.box_overlaps(other_actor_info);
+				if !actor_overlaps.is_empty() {
+					overlaps.push((actor_ref.clone(), actor_overlaps));
+				}
+			}
+		}
+
+		overlaps
+	}
+
 	fn before_move(&mut self, _game_state: &GameState) {}
 	fn after_move(&mut self, _game_state: &GameState) {}
 
 	fn apply_move(&mut self, game_state: &GameState) {
 		self.before_move(game_state);
 
-		match self.move_with_collision(game_state) {
+		self.actor_info_mut().apply_physics_forces();
+		let velocity_x = self.actor_info().velocity_x;
+		let velocity_y = self.actor_info().velocity_y;
+		let result = self.move_with_collision(game_state);
+		self.actor_info_mut().apply_physics_contacts(velocity_x, velocity_y, result.contacts);
+
+		match result.collision {
 			MovementCollision::CollidedWithWorld => self.on_collide_with_world(game_state),
 			MovementCollision::CollidedWithActor(actor) => self.on_collide_with_actor(&actor, game_state),
 			_ => ()
@@ -319,6 +745,14 @@ pub trait Actor: ActorAsAny {
 		self.actor_info_mut().set_sprite_alpha(sprite_index, alpha);
 	}
 
+	fn set_sprite_blend_mode(&mut self, sprite_index: usize, blend_mode: BlendMode) {
+		self.actor_info_mut().set_sprite_blend_mode(sprite_index, blend_mode);
+	}
+
+	fn get_sprite_blend_mode(&mut self, sprite_index: usize) -> BlendMode {
+		self.actor_info_mut().get_sprite_blend_mode(sprite_index)
+	}
+
 	fn get_sprite_alpha(&mut self, sprite_index: usize) -> u8 {
 		self.actor_info_mut().get_sprite_alpha(sprite_index)
 	}
@@ -327,10 +761,54 @@ pub trait Actor: ActorAsAny {
 		self.actor_info_mut().adjust_sprite_alpha(sprite_index, change);
 	}
 
+	fn set_sprite_priority(&mut self, sprite_index: usize, priority: i32) {
+		self.actor_info_mut().set_sprite_priority(sprite_index, priority);
+	}
+
+	fn get_sprite_priority(&mut self, sprite_index: usize) -> i32 {
+		self.actor_info_mut().get_sprite_priority(sprite_index)
+	}
+
+	fn set_sprite_placement(&mut self, sprite_index: usize, placement: SpriteLayerPlacement) {
+		self.actor_info_mut().set_sprite_placement(sprite_index, placement);
+	}
+
+	fn get_sprite_placement(&mut self, sprite_index: usize) -> SpriteLayerPlacement {
+		self.actor_info_mut().get_sprite_placement(sprite_index)
+	}
+
+	fn set_priority(&mut self, priority: i32) {
+		self.actor_info_mut().priority = priority;
+	}
+
 	fn start_animation(&mut self, name: &str) {
 		self.actor_info_mut().start_animation(name);
 	}
 
+	fn queue_animation(&mut self, name: &str) {
+		self.actor_info_mut().queue_animation(name);
+	}
+
+	fn seek_animation(&mut self, frame: usize) {
+		self.actor_info_mut().seek_animation(frame);
+	}
+
+	fn set_animation_speed(&mut self, speed: usize) {
+		self.actor_info_mut().set_animation_speed(speed);
+	}
+
+	fn set_animation_playback_mode(&mut self, mode: AnimationPlaybackMode) {
+		self.actor_info_mut().set_animation_playback_mode(mode);
+	}
+
+	fn get_sprite_animation_frame(&mut self, sprite_index: usize) -> usize {
+		self.actor_info_mut().get_sprite_animation_frame(sprite_index)
+	}
+
+	fn get_sprite_animation_remaining_time(&mut self, sprite_index: usize) -> Option<usize> {
+		self.actor_info_mut().get_sprite_animation_remaining_time(sprite_index)
+	}
+
 	fn set_collision_bounds(&mut self, bounds: BoundingRect) {
 		self.actor_info_mut().set_collision_bounds(bounds);
 	}
@@ -339,6 +817,26 @@ pub trait Actor: ActorAsAny {
 		self.actor_info_mut().clear_collision_bounds();
 	}
 
+	fn set_collision_layer(&mut self, layer: u32, mask: u32) {
+		self.actor_info_mut().set_collision_layer(layer, mask);
+	}
+
+	fn set_trigger(&mut self, trigger: bool) {
+		self.actor_info_mut().set_trigger(trigger);
+	}
+
+	fn set_physics(&mut self, physics: Option<PhysicsBody>) {
+		self.actor_info_mut().set_physics(physics);
+	}
+
+	fn is_grounded(&self) -> bool {
+		self.actor_info().contacts.grounded
+	}
+
+	fn set_platform(&mut self, platform: bool) {
+		self.actor_info_mut().set_platform(platform);
+	}
+
 	fn get_camera_focus_offset(&self) -> (isize, isize) { (0, 0) }
 
 	fn adjust_health(&mut self, amount: i32, game_state: &GameState) {
@@ -364,7 +862,17 @@ pub trait Actor: ActorAsAny {
 	fn on_collide_with_world(&mut self, _game_state: &GameState) {}
 	fn on_collide_with_actor(&mut self, _actor: &ActorRef, _game_state: &GameState) {}
 
+	// Called when a platform pushes or carries the actor into the map
+	fn on_crushed(&mut self, _game_state: &GameState) {}
+
 	fn on_persistent_actor_removed(&mut self, _game_state: &GameState) {}
+
+	fn on_animation_event(&mut self, _name: &str, _game_state: &GameState) {}
+	fn on_animation_finished(&mut self, _name: &str, _game_state: &GameState) {}
+
+	fn on_trigger_enter(&mut self, _zone: &TriggerZoneRef, _game_state: &GameState) {}
+	fn on_trigger_stay(&mut self, _zone: &TriggerZoneRef, _game_state: &GameState) {}
+	fn on_trigger_exit(&mut self, _zone: &TriggerZoneRef, _game_state: &GameState) {}
 }
 
 impl ActorInfo {
@@ -378,9 +886,16 @@ impl ActorInfo {
 			collision_bounds: None,
 			collision_channel: 0,
 			blocking_collision: false,
+			collision_layer: 1,
+			collision_mask: 0xffffffff,
+			trigger: false,
+			platform: false,
+			physics: None,
+			contacts: ContactFlags::default(),
 			sprites: Vec::new(),
 			destroyed: false,
-			health: 100
+			health: 100,
+			priority: 0
 		}
 	}
 
@@ -393,7 +908,14 @@ impl ActorInfo {
 			animation_frame: 0,
 			x_offset, y_offset,
 			blend_mode: BlendMode::Normal,
-			alpha: 0
+			alpha: 0,
+			priority: 0,
+			placement: SpriteLayerPlacement::MainLayer,
+			playback_speed: 0x100,
+			playback_fraction: 0,
+			playback_mode: AnimationPlaybackMode::Forward,
+			queued_animation: None,
+			frame_event_pending: true
 		});
 		index
 	}
@@ -407,7 +929,14 @@ impl ActorInfo {
 			animation,
 			animation_frame: 0,
 			x_offset, y_offset,
-			blend_mode, alpha
+			blend_mode, alpha,
+			priority: 0,
+			placement: SpriteLayerPlacement::MainLayer,
+			playback_speed: 0x100,
+			playback_fraction: 0,
+			playback_mode: AnimationPlaybackMode::Forward,
+			queued_animation: None,
+			frame_event_pending: true
 		});
 		index
 	}
@@ -426,6 +955,48 @@ impl ActorInfo {
 		}
 	}
 
+	pub fn set_sprite_blend_mode(&mut self, sprite_index: usize, blend_mode: BlendMode) {
+		if sprite_index < self.sprites.len() {
+			self.sprites[sprite_index].blend_mode = blend_mode;
+		}
+	}
+
+	pub fn get_sprite_blend_mode(&mut self, sprite_index: usize) -> BlendMode {
+		if sprite_index < self.sprites.len() {
+			self.sprites[sprite_index].blend_mode.clone()
+		} else {
+			BlendMode::Normal
+		}
+	}
+
+	pub fn set_sprite_priority(&mut self, sprite_index: usize, priority: i32) {
+		if sprite_index < self.sprites.len() {
+			self.sprites[sprite_index].priority = priority;
+		}
+	}
+
+	pub fn get_sprite_priority(&mut self, sprite_index: usize) -> i32 {
+		if sprite_index < self.sprites.len() {
+			self.sprites[sprite_index].priority
+		} else {
+			0
+		}
+	}
+
+	pub fn set_sprite_placement(&mut self, sprite_index: usize, placement: SpriteLayerPlacement) {
+		if sprite_index < self.sprites.len() {
+			self.sprites[sprite_index].placement = placement;
+		}
+	}
+
+	pub fn get_sprite_placement(&mut self, sprite_index: usize) -> SpriteLayerPlacement {
+		if sprite_index < self.sprites.len() {
+			self.sprites[sprite_index].placement.clone()
+		} else {
+			SpriteLayerPlacement::MainLayer
+		}
+	}
+
 	pub fn adjust_sprite_alpha(&mut self, sprite_index: usize, change: i8) {
 		let mut alpha = self.get_sprite_alpha(sprite_index);
 		if change > 0 {
@@ -443,20 +1014,318 @@ impl ActorInfo {
 		for sprite in &mut self.sprites {
 			if let Some(animation) = sprite.sprite.get_animation_by_name(name) {
 				if !Rc::ptr_eq(&animation, &sprite.animation) {
-					sprite.animation = animation;
-					sprite.animation_frame = 0;
+					sprite.set_animation(animation);
 				}
 			}
 		}
 	}
 
+	// Starts an animation once the current non-looping animation finishes, or immediately if it has
+	// already finished
+	pub fn queue_animation(&mut self, name: &str) {
+		for sprite in &mut self.sprites {
+			if let Some(animation) = sprite.sprite.get_animation_by_name(name) {
+				if sprite.is_finished() {
+					sprite.set_animation(animation);
+				} else {
+					sprite.queued_animation = Some(animation);
+				}
+			}
+		}
+	}
+
+	pub fn seek_animation(&mut self, frame: usize) {
+		for sprite in &mut self.sprites {
+			sprite.seek(frame);
+		}
+	}
+
+	pub fn set_animation_speed(&mut self, speed: usize) {
+		for sprite in &mut self.sprites {
+			sprite.playback_speed = speed;
+		}
+	}
+
+	pub fn set_animation_playback_mode(&mut self, mode: AnimationPlaybackMode) {
+		for sprite in &mut self.sprites {
+			sprite.playback_mode = mode.clone();
+		}
+	}
+
+	pub fn get_sprite_animation_frame(&mut self, sprite_index: usize) -> usize {
+		if sprite_index < self.sprites.len() {
+			self.sprites[sprite_index].current_frame()
+		} else {
+			0
+		}
+	}
+
+	pub fn get_sprite_animation_remaining_time(&mut self, sprite_index: usize) -> Option<usize> {
+		if sprite_index < self.sprites.len() {
+			self.sprites[sprite_index].remaining_time()
+		} else {
+			None
+		}
+	}
+
 	pub fn set_collision_bounds(&mut self, bounds: BoundingRect) {
 		self.collision_bounds = Some(bounds);
 	}
 
+	pub fn set_collision_layer(&mut self, layer: u32, mask: u32) {
+		self.collision_layer = layer;
+		self.collision_mask = mask;
+	}
+
+	pub fn set_trigger(&mut self, trigger: bool) {
+		self.trigger = trigger;
+	}
+
+	pub fn set_platform(&mut self, platform: bool) {
+		self.platform = platform;
+	}
+
+	// Actors standing on top of the platform bounds. Other platforms are not carried.
+	fn find_riders(&self, bounds: &BoundingRect, game_state: &GameState) -> Vec<ActorRef> {
+		let top_edge = BoundingRect {
+			x: bounds.x,
+			y: bounds.y - 1,
+			width: bounds.width,
+			height: 1
+		};
+		game_state.actors_in_rect(&top_edge, None).into_iter().filter(|actor_ref| {
+			let other = actor_ref.borrow();
+			let other_info = other.actor_info();
+			if other_info.platform || !self.is_blocked_by(other_info) {
+				return false;
+			}
+			match other_info.world_collision_bounds() {
+				Some(other_bounds) => (other_bounds.y + other_bounds.height) == bounds.y,
+				None => false
+			}
+		}).collect()
+	}
+
+	// Moves an actor by the given amount, stopping at the map. Returns whether the movement was
+	// stopped on each axis.
+	fn move_by_platform(&mut self, dx: isize, dy: isize, game_state: &GameState) -> (bool, bool) {
+		let mut bounds = match self.world_collision_bounds() {
+			Some(bounds) => bounds,
+			None => return (false, false)
+		};
+		let start_x = bounds.x;
+		let start_y = bounds.y;
+		let mut target_x = bounds.x + dx;
+		let mut target_y = bounds.y + dy;
+		let mut blocked_x = false;
+		let mut blocked_y = false;
+
+		if let Some(map) = &*game_state.map.borrow() {
+			if dx != 0 {
+				if let Some(revised_x) = map.sweep_collision_x(&bounds, target_x, self.collision_channel) {
+					target_x = revised_x;
+					blocked_x = true;
+				}
+			}
+			bounds.x = target_x;
+			if dy != 0 {
+				if let Some(revised_y) = map.sweep_collision_y(&bounds, target_y, self.collision_channel) {
+					target_y = revised_y;
+					blocked_y = true;
+				}
+			}
+		}
+
+		self.x += target_x - start_x;
+		self.y += target_y - start_y;
+		(blocked_x, blocked_y)
+	}
+
+	// Carries riders along with a platform that moved from `prev_bounds`, and pushes actors the platform
+	// moved into out of its way. Actors that cannot move out of the way are crushed. Carried and pushed
+	// actors are stopped by the map but not by other actors.
+	fn move_riders(&self, prev_bounds: &BoundingRect, riders: &[ActorRef], game_state: &GameState) {
+		let bounds = match self.world_collision_bounds() {
+			Some(bounds) => bounds,
+			None => return
+		};
+		let dx = bounds.x - prev_bounds.x;
+		let dy = bounds.y - prev_bounds.y;
+		if (dx == 0) && (dy == 0) {
+			return;
+		}
+
+		let mut crushed = Vec::new();
+		for rider_ref in riders {
+			if let Ok(mut rider) = rider_ref.try_borrow_mut() {
+				let (_, blocked_y) = rider.actor_info_mut().move_by_platform(dx, dy, game_state);
+				game_state.update_actor_bounds(rider_ref, rider.actor_info().world_collision_bounds());
+				if blocked_y && (dy < 0) {
+					crushed.push(rider_ref.clone());
+				}
+			}
+		}
+
+		let sweep_area = prev_bounds.sweep_area(bounds.x, bounds.y);
+		for actor_ref in &game_state.actors_in_rect(&sweep_area, None) {
+			if riders.iter().any(|rider| Rc::ptr_eq(rider, actor_ref)) {
+				continue;
+			}
+			if let Ok(mut other) = actor_ref.try_borrow_mut() {
+				let other_info = other.actor_info_mut();
+				if other_info.platform || !self.is_blocked_by(other_info) {
+					continue;
+				}
+				let other_bounds = match other_info.world_collision_bounds() {
+					Some(other_bounds) => other_bounds,
+					None => continue
+				};
+				if prev_bounds.is_colliding(&other_bounds) || !bounds.is_colliding(&other_bounds) {
+					// Actors already inside the platform are left alone
+					continue;
+				}
+
+				// Push along the axis the actor was approached from
+				let above_or_below = ((other_bounds.y + other_bounds.height) <= prev_bounds.y) ||
+					(other_bounds.y >= (prev_bounds.y + prev_bounds.height));
+				let blocked = if above_or_below {
+					let push_y = if dy > 0 {
+						(bounds.y + bounds.height) - other_bounds.y
+					} else {
+						bounds.y - (other_bounds.y + other_bounds.height)
+					};
+					other_info.move_by_platform(0, push_y, game_state).1
+				} else {
+					let push_x = if dx > 0 {
+						(bounds.x + bounds.width) - other_bounds.x
+					} else {
+						bounds.x - (other_bounds.x + other_bounds.width)
+					};
+					other_info.move_by_platform(push_x, 0, game_state).0
+				};
+				game_state.update_actor_bounds(actor_ref, other_info.world_collision_bounds());
+				if blocked {
+					crushed.push(actor_ref.clone());
+				}
+			}
+		}
+
+		for actor_ref in &crushed {
+			actor_ref.borrow_mut().on_crushed(game_state);
+		}
+	}
+
+	pub fn set_physics(&mut self, physics: Option<PhysicsBody>) {
+		self.physics = physics;
+	}
+
+	// Applies gravity, and friction or drag depending on whether the last move ended on the ground
+	pub fn apply_physics_forces(&mut self) {
+		if let Some(physics) = &self.physics {
+			self.velocity_y = min(self.velocity_y + physics.gravity, max(physics.max_fall_speed, self.velocity_y));
+			let slowdown = if self.contacts.grounded { physics.ground_friction } else { physics.air_drag };
+			self.velocity_x = PhysicsBody::slow_down(self.velocity_x, slowdown);
+		}
+	}
+
+	// Records the contacts from a move, bouncing the velocity from before the move off of the surfaces hit
+	pub fn apply_physics_contacts(&mut self, velocity_x: isize, velocity_y: isize, contacts: ContactFlags) {
+		self.contacts = contacts;
+		if let Some(physics) = &self.physics {
+			if physics.restitution == 0 {
+				return;
+			}
+			if (contacts.wall_left && (velocity_x < 0)) || (contacts.wall_right && (velocity_x > 0)) {
+				self.velocity_x = physics.bounce(velocity_x);
+			}
+			if (contacts.grounded && (velocity_y > 0)) || (contacts.ceiling && (velocity_y < 0)) {
+				self.velocity_y = physics.bounce(velocity_y);
+			}
+		}
+	}
+
+	pub fn can_collide_with(&self, other: &ActorInfo) -> bool {
+		((self.collision_mask & other.collision_layer) != 0) && ((other.collision_mask & self.collision_layer) != 0)
+	}
+
+	pub fn is_blocked_by(&self, other: &ActorInfo) -> bool {
+		!self.trigger && !other.trigger && self.can_collide_with(other)
+	}
+
+	// Collision bounds at the actor's position, in map coordinates
+	pub fn world_collision_bounds(&self) -> Option<BoundingRect> {
+		self.collision_bounds.as_ref().map(|bounds| BoundingRect {
+			x: self.x + bounds.x,
+			y: self.y + bounds.y,
+			width: bounds.width,
+			height: bounds.height
+		})
+	}
+
+	// Animation boxes for the current frame of each sprite, in map coordinates
+	pub fn animation_boxes(&self) -> Vec<AnimationBox> {
+		let mut boxes = Vec::new();
+		for sprite in &self.sprites {
+			for animation_box in sprite.animation.boxes_for_frame(sprite.current_frame()) {
+				boxes.push(AnimationBox {
+					kind: animation_box.kind.clone(),
+					channel: animation_box.channel,
+					bounds: BoundingRect {
+						x: self.x + sprite.x_offset + animation_box.bounds.x,
+						y: self.y + sprite.y_offset + animation_box.bounds.y,
+						width: animation_box.bounds.width,
+						height: animation_box.bounds.height
+					}
+				});
+			}
+		}
+		boxes
+	}
+
+	// Finds the kinds of animation boxes on the same channel that overlap between two actors. Each
+	// combination of kinds and channel is reported once.
+	pub fn box_overlaps(&self, other: &ActorInfo) -> Vec<BoxOverlap> {
+		let mut overlaps = Vec::new();
+		let other_boxes = other.animation_boxes();
+		if other_boxes.is_empty() {
+			return overlaps;
+		}
+
+		for animation_box in self.animation_boxes() {
+			for other_box in &other_boxes {
+				if (animation_box.channel != other_box.channel) ||
+					!animation_box.bounds.is_colliding(&other_box.bounds) {
+					continue;
+				}
+				let overlap = BoxOverlap {
+					kind: animation_box.kind.clone(),
+					other_kind: other_box.kind.clone(),
+					channel: animation_box.channel
+				};
+				if !overlaps.contains(&overlap) {
+					overlaps.push(overlap);
+				}
+			}
+		}
+		overlaps
+	}
+
 	pub fn clear_collision_bounds(&mut self) {
 		self.collision_bounds = None;
 	}
+
+	// Position of the actor's feet used for y sorting. This is the bottom of the collision bounds, or the
+	// bottom of the lowest sprite frame for actors without collision bounds.
+	pub fn depth_sort_y(&self) -> isize {
+		if let Some(bounds) = &self.collision_bounds {
+			return self.y + bounds.y + bounds.height;
+		}
+		let sprite_bottom = self.sprites.iter().map(|sprite| {
+			let layout = sprite.animation.frame_layout_for_time(sprite.animation_time());
+			sprite.y_offset - layout.origin_y + layout.height as isize
+		}).max();
+		self.y + sprite_bottom.unwrap_or(0)
+	}
 }
 
 impl<T: Actor + 'static> ActorAsAny for T {
@@ -468,3 +1337,68 @@ impl<T: Actor + 'static> ActorAsAny for T {
 		self
 	}
 }
+
+#[cfg(test)]
+pub mod tests {
+	use std::cell::RefCell;
+	use std::rc::Rc;
+	use game::tests::test_game_state;
+	use super::{Actor, ActorInfo, ActorRef, BoundingRect};
+
+	pub struct TestActor {
+		info: ActorInfo
+	}
+
+	impl Actor for TestActor {
+		fn actor_info(&self) -> &ActorInfo {
+			&self.info
+		}
+
+		fn actor_info_mut(&mut self) -> &mut ActorInfo {
+			&mut self.info
+		}
+	}
+
+	// Actor with collision bounds of the given size at its position
+	pub fn test_actor(x: isize, y: isize, width: isize, height: isize) -> ActorRef {
+		let mut info = ActorInfo::new(x, y);
+		info.collision_bounds = Some(BoundingRect { x: 0, y: 0, width, height });
+		Rc::new(RefCell::new(Box::new(TestActor { info })))
+	}
+
+	#[test]
+	fn y_sorting_uses_bottom_edge() {
+		let tall = test_actor(0, 32, 16, 32);
+		let short = test_actor(16, 48, 16, 16);
+		let lower = test_actor(32, 40, 16, 32);
+		assert_eq!(tall.borrow().actor_info().depth_sort_y(), 64);
+		assert_eq!(short.borrow().actor_info().depth_sort_y(), 64);
+		// Top edge is higher than the short actor's, but it stands further down
+		assert!(lower.borrow().actor_info().depth_sort_y() > short.borrow().actor_info().depth_sort_y());
+	}
+
+	#[test]
+	fn carried_rider_is_found_at_new_position() {
+		let mut game_state = test_game_state();
+		let platform = test_actor(0, 100, 64, 16);
+		{
+			let mut platform = platform.borrow_mut();
+			let platform_info = platform.actor_info_mut();
+			platform_info.platform = true;
+			platform_info.blocking_collision = true;
+			platform_info.velocity_x = 200 << 8;
+			platform_info.velocity_y = -(40 << 8);
+		}
+		let rider = test_actor(8, 84, 16, 16);
+		game_state.actors = vec![platform.clone(), rider.clone()];
+		game_state.spatial_grid.borrow_mut().rebuild(&game_state.actors);
+
+		platform.borrow_mut().apply_move(&game_state);
+
+		assert_eq!((rider.borrow().actor_info().x, rider.borrow().actor_info().y), (208, 44));
+		let found = game_state.actors_in_rect(&BoundingRect { x: 208, y: 44, width: 16, height: 16 }, None);
+		assert!(found.iter().any(|actor| Rc::ptr_eq(actor, &rider)));
+		let found = game_state.actors_in_rect(&BoundingRect { x: 8, y: 84, width: 16, height: 16 }, None);
+		assert!(!found.iter().any(|actor| Rc::ptr_eq(actor, &rider)));
+	}
+}
@@ -0,0 +1,250 @@
+extern crate png;
+extern crate gif;
+
+use std::io;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use render::FrameBuffer;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordingFormat {
+	Gif,
+	FrameSequence
+}
+
+pub struct Recording {
+	pub format: RecordingFormat,
+	pub path: String,
+	pub frame_interval: usize,
+	pub frames: usize,
+	width: usize,
+	height: usize,
+	next_frame: Option<usize>,
+	gif_frames: Option<mpsc::SyncSender<GifFrame>>,
+	gif_thread: Option<thread::JoinHandle<Result<(), io::Error>>>
+}
+
+// Maximum number of captured frames waiting for the GIF encoder before recording blocks the game loop
+const GIF_QUEUE_FRAMES: usize = 120;
+
+struct GifFrame {
+	buf: FrameBuffer,
+	delay: u16
+}
+
+// Finds an unused file name in the capture directory, named after the current time
+fn unique_capture_path(directory: &str, prefix: &str, extension: &str) -> String {
+	let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+		Ok(duration) => duration.as_secs(),
+		Err(_) => 0
+	};
+	let mut path = format!("{}/{}-{}{}", directory, prefix, timestamp, extension);
+	let mut index = 1;
+	while Path::new(&path).exists() {
+		index += 1;
+		path = format!("{}/{}-{}-{}{}", directory, prefix, timestamp, index, extension);
+	}
+	path
+}
+
+fn rgb_data(buf: &FrameBuffer) -> Vec<u8> {
+	let mut data = Vec::with_capacity(buf.width * buf.height * 3);
+	for y in 0..buf.height {
+		for pixel in buf.row(y) {
+			data.push((pixel >> 16) as u8);
+			data.push((pixel >> 8) as u8);
+			data.push(*pixel as u8);
+		}
+	}
+	data
+}
+
+// Converts the buffer to a palette and indexed pixels, if it has few enough colors to be represented
+// exactly. Frames usually qualify as the renderer works with 15-bit palette based assets.
+fn indexed_data(buf: &FrameBuffer) -> Option<(Vec<u8>, Vec<u8>)> {
+	let mut palette = Vec::new();
+	let mut indices = HashMap::new();
+	let mut pixels = Vec::with_capacity(buf.width * buf.height);
+	for y in 0..buf.height {
+		for pixel in buf.row(y) {
+			let color = pixel & 0xffffff;
+			let index = match indices.get(&color) {
+				Some(index) => *index,
+				None => {
+					if indices.len() >= 256 {
+						return None;
+					}
+					let index = indices.len() as u8;
+					indices.insert(color, index);
+					palette.push((color >> 16) as u8);
+					palette.push((color >> 8) as u8);
+					palette.push(color as u8);
+					index
+				}
+			};
+			pixels.push(index);
+		}
+	}
+	Some((palette, pixels))
+}
+
+pub fn write_png(path: &str, buf: &FrameBuffer) -> Result<(), io::Error> {
+	use self::png::HasParameters;
+	let file = BufWriter::new(File::create(path)?);
+	let mut encoder = png::Encoder::new(file, buf.width as u32, buf.height as u32);
+	encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+	let mut writer = encoder.write_header()?;
+	writer.write_image_data(&rgb_data(buf))?;
+	Ok(())
+}
+
+// Writes a PNG screenshot into the capture directory, returning the path of the new file
+pub fn save_screenshot(directory: &str, buf: &FrameBuffer) -> Result<String, io::Error> {
+	fs::create_dir_all(directory)?;
+	let path = unique_capture_path(directory, "screenshot", ".png");
+	write_png(&path, buf)?;
+	Ok(path)
+}
+
+fn gif_frame(frame: &GifFrame) -> gif::Frame<'static> {
+	let buf = &frame.buf;
+	let mut gif_frame = match indexed_data(buf) {
+		Some((palette, pixels)) =>
+			gif::Frame::from_palette_pixels(buf.width as u16, buf.height as u16, &pixels, &palette, None),
+		None => gif::Frame::from_rgb_speed(buf.width as u16, buf.height as u16, &rgb_data(buf), 10)
+	};
+	gif_frame.delay = frame.delay;
+	gif_frame
+}
+
+// Runs on the recording's encoder thread, writing frames until the recording is finished. The file is
+// created when the first frame arrives.
+fn encode_gif(path: &str, frames: mpsc::Receiver<GifFrame>) -> Result<(), io::Error> {
+	use self::gif::SetParameter;
+	let first = match frames.recv() {
+		Ok(frame) => frame,
+		Err(_) => return Ok(())
+	};
+
+	let mut file = BufWriter::new(File::create(path)?);
+	{
+		let mut encoder = gif::Encoder::new(&mut file, first.buf.width as u16, first.buf.height as u16, &[])?;
+		encoder.set(gif::Repeat::Infinite)?;
+		encoder.write_frame(&gif_frame(&first))?;
+		for frame in frames {
+			encoder.write_frame(&gif_frame(&frame))?;
+		}
+		// Dropping the encoder writes the GIF trailer
+	}
+	file.flush()
+}
+
+impl Recording {
+	// Starts a recording in the capture directory. Every `frame_interval` game frames are captured, so
+	// an interval of 2 records at 30 frames per second.
+	pub fn start(directory: &str, format: RecordingFormat, frame_interval: usize) -> Result<Recording, io::Error> {
+		fs::create_dir_all(directory)?;
+		let path = match format {
+			RecordingFormat::Gif => unique_capture_path(directory, "recording", ".gif"),
+			RecordingFormat::FrameSequence => {
+				let path = unique_capture_path(directory, "recording", "");
+				fs::create_dir_all(&path)?;
+				path
+			}
+		};
+
+		// GIF frames are quantized and compressed on a separate thread so the game loop isn't stalled
+		let (gif_frames, gif_thread) = match format {
+			RecordingFormat::Gif => {
+				let (sender, receiver) = mpsc::sync_channel(GIF_QUEUE_FRAMES);
+				let gif_path = path.clone();
+				let thread = thread::Builder::new()
+					.name("gif encoder".to_string())
+					.spawn(move || encode_gif(&gif_path, receiver))?;
+				(Some(sender), Some(thread))
+			},
+			RecordingFormat::FrameSequence => (None, None)
+		};
+
+		Ok(Recording {
+			format, path,
+			frame_interval: frame_interval.max(1),
+			frames: 0,
+			width: 0,
+			height: 0,
+			next_frame: None,
+			gif_frames, gif_thread
+		})
+	}
+
+	pub fn add_frame(&mut self, buf: &FrameBuffer, frame: usize) -> Result<(), io::Error> {
+		if let Some(next_frame) = self.next_frame {
+			if frame < next_frame {
+				return Ok(());
+			}
+		}
+		self.next_frame = Some(frame + self.frame_interval);
+
+		if self.frames == 0 {
+			self.width = buf.width;
+			self.height = buf.height;
+		} else if (buf.width != self.width) || (buf.height != self.height) {
+			// Resolution changed during recording, frames must all be the same size
+			return Ok(());
+		}
+
+		match self.format {
+			RecordingFormat::Gif => self.add_gif_frame(buf)?,
+			RecordingFormat::FrameSequence => write_png(&format!("{}/frame-{:06}.png", self.path, self.frames), buf)?
+		};
+		self.frames += 1;
+		Ok(())
+	}
+
+	fn add_gif_frame(&mut self, buf: &FrameBuffer) -> Result<(), io::Error> {
+		let mut copy = FrameBuffer::new(buf.width, buf.height);
+		for y in 0..buf.height {
+			copy.row_mut(y).copy_from_slice(buf.row(y));
+		}
+
+		// Delays are in hundredths of a second, spread rounding so the total matches 60 frames per second
+		let start = (self.frames * self.frame_interval * 100) / 60;
+		let end = ((self.frames + 1) * self.frame_interval * 100) / 60;
+		let frame = GifFrame { buf: copy, delay: (end - start) as u16 };
+
+		let sent = match &self.gif_frames {
+			Some(sender) => sender.send(frame).is_ok(),
+			None => false
+		};
+		if !sent {
+			// The encoder thread only stops early when it fails, report its error
+			self.finish_gif()?;
+			return Err(io::Error::other("GIF encoder stopped"));
+		}
+		Ok(())
+	}
+
+	// Waits for queued frames to be written and the GIF to be completed
+	fn finish_gif(&mut self) -> Result<(), io::Error> {
+		self.gif_frames = None;
+		match self.gif_thread.take() {
+			Some(thread) => match thread.join() {
+				Ok(result) => result,
+				Err(_) => Err(io::Error::other("GIF encoder thread panicked"))
+			},
+			None => Ok(())
+		}
+	}
+
+	// Completes the recording, returning the path of the written file or frame directory
+	pub fn finish(mut self) -> Result<String, io::Error> {
+		self.finish_gif()?;
+		Ok(self.path)
+	}
+}
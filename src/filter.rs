@@ -0,0 +1,234 @@
+use std::cmp::{min, max};
+use render::{FrameBuffer, RenderSize};
+
+// Largest scale used by filters that pick their scale from the window size, higher scales are left to
+// the final GPU upscale to keep CPU cost bounded
+const MAX_AUTO_SCALE: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpscaleFilter {
+	None,
+	Nearest,
+	Scanlines,
+	CrtMask,
+	Scale2x,
+	Scale3x,
+	Xbr2x
+}
+
+impl UpscaleFilter {
+	pub fn scale(&self, render_size: &RenderSize, dest_size: &RenderSize) -> usize {
+		let auto_scale = min(dest_size.height.checked_div(render_size.height).unwrap_or(1), MAX_AUTO_SCALE);
+		match self {
+			UpscaleFilter::None => 1,
+			UpscaleFilter::Nearest => max(auto_scale, 1),
+			UpscaleFilter::Scanlines => max(auto_scale, 2),
+			UpscaleFilter::CrtMask => max(auto_scale, 3),
+			UpscaleFilter::Scale2x => 2,
+			UpscaleFilter::Scale3x => 3,
+			UpscaleFilter::Xbr2x => 2
+		}
+	}
+
+	// Upscales `src` into `dest`, resizing `dest` to the filtered size
+	pub fn apply(&self, src: &FrameBuffer, dest: &mut FrameBuffer, dest_size: &RenderSize) {
+		let scale = self.scale(&src.size(), dest_size);
+		dest.resize(src.width * scale, src.height * scale);
+		match self {
+			UpscaleFilter::None | UpscaleFilter::Nearest => nearest(src, dest, scale),
+			UpscaleFilter::Scanlines => scanlines(src, dest, scale),
+			UpscaleFilter::CrtMask => crt_mask(src, dest, scale),
+			UpscaleFilter::Scale2x => scale2x(src, dest),
+			UpscaleFilter::Scale3x => scale3x(src, dest),
+			UpscaleFilter::Xbr2x => xbr2x(src, dest)
+		};
+	}
+}
+
+fn pixel_at(src: &FrameBuffer, x: isize, y: isize) -> u32 {
+	let x = min(max(x, 0), src.width as isize - 1) as usize;
+	let y = min(max(y, 0), src.height as isize - 1) as usize;
+	src.row(y)[x]
+}
+
+// Scales each channel by a fraction out of 256
+fn scale_channels(color: u32, r_scale: u32, g_scale: u32, b_scale: u32) -> u32 {
+	let r = (((color >> 16) & 0xff) * r_scale) >> 8;
+	let g = (((color >> 8) & 0xff) * g_scale) >> 8;
+	let b = ((color & 0xff) * b_scale) >> 8;
+	(r << 16) | (g << 8) | b
+}
+
+fn average(a: u32, b: u32) -> u32 {
+	((a & 0xfefefe) + (b & 0xfefefe)) >> 1
+}
+
+fn nearest(src: &FrameBuffer, dest: &mut FrameBuffer, scale: usize) {
+	for y in 0..src.height {
+		{
+			let dest_row = dest.row_mut(y * scale);
+			for (x, pixel) in src.row(y).iter().enumerate() {
+				for dest_pixel in &mut dest_row[x * scale .. (x + 1) * scale] {
+					*dest_pixel = *pixel;
+				}
+			}
+		}
+		for i in 1..scale {
+			let (first, rest) = dest.pixels.split_at_mut((y * scale + i) * dest.stride);
+			let source_start = y * scale * dest.stride;
+			rest[.. dest.width].copy_from_slice(&first[source_start .. source_start + dest.width]);
+		}
+	}
+}
+
+fn scanlines(src: &FrameBuffer, dest: &mut FrameBuffer, scale: usize) {
+	nearest(src, dest, scale);
+
+	// Darken the last row of each source row
+	for y in 0..src.height {
+		for pixel in dest.row_mut((y * scale) + scale - 1) {
+			*pixel = scale_channels(*pixel, 128, 128, 128);
+		}
+	}
+}
+
+fn crt_mask(src: &FrameBuffer, dest: &mut FrameBuffer, scale: usize) {
+	nearest(src, dest, scale);
+
+	// Aperture grille emphasizing one channel per column, with darkened gaps between rows
+	for y in 0..dest.height {
+		let scanline = (y % scale) == (scale - 1);
+		for (x, pixel) in dest.row_mut(y).iter_mut().enumerate() {
+			let color = match x % 3 {
+				0 => scale_channels(*pixel, 256, 160, 160),
+				1 => scale_channels(*pixel, 160, 256, 160),
+				_ => scale_channels(*pixel, 160, 160, 256)
+			};
+			*pixel = if scanline {
+				scale_channels(color, 160, 160, 160)
+			} else {
+				color
+			};
+		}
+	}
+}
+
+fn scale2x(src: &FrameBuffer, dest: &mut FrameBuffer) {
+	for y in 0..src.height as isize {
+		for x in 0..src.width as isize {
+			let b = pixel_at(src, x, y - 1);
+			let d = pixel_at(src, x - 1, y);
+			let e = pixel_at(src, x, y);
+			let f = pixel_at(src, x + 1, y);
+			let h = pixel_at(src, x, y + 1);
+
+			let (e0, e1, e2, e3) = if (b != h) && (d != f) {
+				(if d == b { d } else { e },
+					if b == f { f } else { e },
+					if d == h { d } else { e },
+					if h == f { f } else { e })
+			} else {
+				(e, e, e, e)
+			};
+
+			let dest_x = x as usize * 2;
+			let dest_y = y as usize * 2;
+			dest.row_mut(dest_y)[dest_x] = e0;
+			dest.row_mut(dest_y)[dest_x + 1] = e1;
+			dest.row_mut(dest_y + 1)[dest_x] = e2;
+			dest.row_mut(dest_y + 1)[dest_x + 1] = e3;
+		}
+	}
+}
+
+fn scale3x(src: &FrameBuffer, dest: &mut FrameBuffer) {
+	for y in 0..src.height as isize {
+		for x in 0..src.width as isize {
+			let a = pixel_at(src, x - 1, y - 1);
+			let b = pixel_at(src, x, y - 1);
+			let c = pixel_at(src, x + 1, y - 1);
+			let d = pixel_at(src, x - 1, y);
+			let e = pixel_at(src, x, y);
+			let f = pixel_at(src, x + 1, y);
+			let g = pixel_at(src, x - 1, y + 1);
+			let h = pixel_at(src, x, y + 1);
+			let i = pixel_at(src, x + 1, y + 1);
+
+			let out = if (b != h) && (d != f) {
+				[
+					if d == b { d } else { e },
+					if ((d == b) && (e != c)) || ((b == f) && (e != a)) { b } else { e },
+					if b == f { f } else { e },
+					if ((d == b) && (e != g)) || ((d == h) && (e != a)) { d } else { e },
+					e,
+					if ((b == f) && (e != i)) || ((h == f) && (e != c)) { f } else { e },
+					if d == h { d } else { e },
+					if ((d == h) && (e != i)) || ((h == f) && (e != g)) { h } else { e },
+					if h == f { f } else { e }
+				]
+			} else {
+				[e; 9]
+			};
+
+			let dest_x = x as usize * 3;
+			let dest_y = y as usize * 3;
+			for (row, colors) in out.chunks(3).enumerate() {
+				dest.row_span_mut(dest_y + row, dest_x, 3).copy_from_slice(colors);
+			}
+		}
+	}
+}
+
+// Perceptual distance between two colors, weighting luma over chroma
+fn yuv_distance(a: u32, b: u32) -> i32 {
+	let dr = ((a >> 16) & 0xff) as i32 - ((b >> 16) & 0xff) as i32;
+	let dg = ((a >> 8) & 0xff) as i32 - ((b >> 8) & 0xff) as i32;
+	let db = (a & 0xff) as i32 - (b & 0xff) as i32;
+	let y = ((dr * 299) + (dg * 587) + (db * 114)) / 1000;
+	let u = ((dr * -169) + (dg * -331) + (db * 500)) / 1000;
+	let v = ((dr * 500) + (dg * -419) + (db * -81)) / 1000;
+	(y.abs() * 48) + (u.abs() * 7) + (v.abs() * 6)
+}
+
+// Computes the output pixel for one corner of the 2x output using xBR edge detection. Offsets are
+// mirrored by `dir_x` and `dir_y` so that the same rule handles all four corners.
+fn xbr_corner(src: &FrameBuffer, x: isize, y: isize, dir_x: isize, dir_y: isize) -> u32 {
+	let p = |dx: isize, dy: isize| pixel_at(src, x + (dx * dir_x), y + (dy * dir_y));
+	let e = p(0, 0);
+	let c = p(1, -1);
+	let g = p(-1, 1);
+	let d = p(-1, 0);
+	let b = p(0, -1);
+	let f = p(1, 0);
+	let h = p(0, 1);
+	let i = p(1, 1);
+	let f4 = p(2, 0);
+	let h5 = p(0, 2);
+	let i4 = p(2, 1);
+	let i5 = p(1, 2);
+
+	// Compare the strength of edges along both diagonals through the corner
+	let edge_e = yuv_distance(e, c) + yuv_distance(e, g) + yuv_distance(i, f4) + yuv_distance(i, h5) +
+		(4 * yuv_distance(h, f));
+	let edge_i = yuv_distance(h, d) + yuv_distance(h, i5) + yuv_distance(f, i4) + yuv_distance(f, b) +
+		(4 * yuv_distance(e, i));
+	if (edge_e < edge_i) && (e != f) && (e != h) {
+		let new_pixel = if yuv_distance(e, f) <= yuv_distance(e, h) { f } else { h };
+		average(e, new_pixel)
+	} else {
+		e
+	}
+}
+
+fn xbr2x(src: &FrameBuffer, dest: &mut FrameBuffer) {
+	for y in 0..src.height as isize {
+		for x in 0..src.width as isize {
+			let dest_x = x as usize * 2;
+			let dest_y = y as usize * 2;
+			dest.row_mut(dest_y)[dest_x] = xbr_corner(src, x, y, -1, -1);
+			dest.row_mut(dest_y)[dest_x + 1] = xbr_corner(src, x, y, 1, -1);
+			dest.row_mut(dest_y + 1)[dest_x] = xbr_corner(src, x, y, -1, 1);
+			dest.row_mut(dest_y + 1)[dest_x + 1] = xbr_corner(src, x, y, 1, 1);
+		}
+	}
+}
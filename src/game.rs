@@ -1,5 +1,5 @@
 extern crate sdl2;
-extern crate byteorder;
+extern crate rayon;
 
 #[cfg(target_os = "emscripten")]
 use emscripten::emscripten;
@@ -9,6 +9,7 @@ use std::os::raw::c_char;
 use self::sdl2::filesystem;
 
 use self::sdl2::EventPump;
+use self::rayon::ThreadPool;
 use self::sdl2::event::{Event, WindowEvent};
 use self::sdl2::keyboard::{Keycode, Mod};
 use self::sdl2::mouse::MouseButton;
@@ -16,24 +17,35 @@ use self::sdl2::joystick::{Joystick, HatState};
 use self::sdl2::pixels::PixelFormatEnum;
 use self::sdl2::rect::Rect;
 use self::sdl2::render::{Canvas, Texture};
-use self::sdl2::video::Window;
+use self::sdl2::video::{Window, FullscreenType};
 #[cfg(target_os = "macos")]
 use self::sdl2::video::WindowPos;
 use self::sdl2::clipboard::ClipboardUtil;
 use self::sdl2::audio::{AudioSpecDesired, AudioDevice};
-use self::byteorder::{ByteOrder, LittleEndian};
 use std::process;
+use std::mem;
+use std::cmp::min;
+use std::io;
+use std::fmt;
+use std::path::Path;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::time::{Instant, Duration};
 use std::thread::sleep;
 use render;
-use render::{RenderSize, ResolutionTarget};
-use map::{Map, MapActor};
+use render::{RenderSize, ResolutionTarget, LayerCache, FrameBuffer, DisplaySettings, WindowMode};
+use map::{Map, MapActor, MapTileChange, TileArea, TileRef, LayerAnimationState};
 use ui::{UILayoutRef, UILayerRef};
-use actor::{Actor, ActorRef};
+use actor::{Actor, ActorRef, DepthSortMode, BoundingRect};
 use camera::Camera;
+use viewport::{Viewport, ViewportRef};
+use spatial::{SpatialGrid, ActorRaycastHit};
+use spatial;
+use trigger::{TriggerZone, TriggerZoneRef};
+use capture;
+use filter::UpscaleFilter;
+use capture::{Recording, RecordingFormat};
 use asset::AssetNamespace;
 use audio;
 use audio::{AudioMixer, AudioMixerCallback, AudioMixerRef, Sound, SoundRef, MonoWavAudioSource};
@@ -75,6 +87,26 @@ pub struct SetControlledActorEvent {
 	actor: Option<ActorRef>
 }
 
+#[derive(Clone)]
+pub struct AddViewportEvent {
+	viewport: ViewportRef
+}
+
+#[derive(Clone)]
+pub struct RemoveViewportEvent {
+	viewport: ViewportRef
+}
+
+#[derive(Clone)]
+pub struct AddTriggerZoneEvent {
+	zone: TriggerZoneRef
+}
+
+#[derive(Clone)]
+pub struct RemoveTriggerZoneEvent {
+	zone: TriggerZoneRef
+}
+
 #[derive(Clone)]
 pub struct SetCameraEvent {
 	camera: Option<Camera>
@@ -113,6 +145,11 @@ pub enum PendingEvent {
 	AddUILayout(AddUILayoutEvent),
 	RemoveUILayout(RemoveUILayoutEvent),
 	SetControlledActor(SetControlledActorEvent),
+	AddViewport(AddViewportEvent),
+	RemoveViewport(RemoveViewportEvent),
+	ClearViewports,
+	AddTriggerZone(AddTriggerZoneEvent),
+	RemoveTriggerZone(RemoveTriggerZoneEvent),
 	SetCamera(SetCameraEvent),
 	SetCameraShake(SetCameraShakeEvent),
 	SetScroll(SetScrollEvent),
@@ -124,14 +161,17 @@ pub enum PendingEvent {
 
 pub struct GameState {
 	pub assets: AssetNamespace,
-	pub map: Option<Map>,
+	pub map: RefCell<Option<Map>>,
 	pub ui_layouts: Vec<UILayoutRef>,
 	pub actors: Vec<ActorRef>,
 	pub persistent_actors: Vec<ActorRef>,
+	pub spatial_grid: RefCell<SpatialGrid>,
+	pub trigger_zones: Vec<TriggerZoneRef>,
 	pub controlled_actor: Option<ActorRef>,
 	pub camera: Option<Camera>,
 	pub camera_shake_x: isize,
 	pub camera_shake_y: isize,
+	pub viewports: Vec<ViewportRef>,
 	pub render_size: RenderSize,
 	pub scroll_x: isize,
 	pub scroll_y: isize,
@@ -139,6 +179,18 @@ pub struct GameState {
 	pub target_fade_alpha: u8,
 	pub frame: usize,
 	pub rendered_frame: usize,
+	pub render_time: Duration,
+	pub layer_caching: bool,
+	pub layer_caches: RefCell<Vec<LayerCache>>,
+	pub tile_animations: RefCell<Vec<LayerAnimationState>>,
+	// When enabled, tile changes are kept by map id and applied again whenever the map is loaded. Changes
+	// are keyed by layer and tile position.
+	pub record_map_changes: bool,
+	pub map_changes: RefCell<HashMap<String, HashMap<(usize, usize, usize), MapTileChange>>>,
+	pub map_changes_error: RefCell<Option<io::Error>>,
+	pub render_threads: usize,
+	pub depth_sort_mode: DepthSortMode,
+	pub render_thread_pool: RefCell<Option<ThreadPool>>,
 	pub key_bindings: HashMap<Keycode, String>,
 	pub axis_bindings: HashMap<u8, String>,
 	pub button_bindings: HashMap<u8, String>,
@@ -148,6 +200,7 @@ pub struct GameState {
 	pub ui_button_bindings: HashMap<u8, String>,
 	pub ui_hat_bindings: HashMap<u8, HatBindings>,
 	pub actor_loaders: HashMap<String, Box<Fn(&MapActor, &AssetNamespace) -> Option<Box<Actor>>>>,
+	pub trigger_zone_types: Vec<String>,
 	pub pending_events: RefCell<Vec<PendingEvent>>,
 	pub last_click_frame: Option<usize>,
 	pub last_click_button: Option<MouseButton>,
@@ -160,7 +213,18 @@ pub struct GameState {
 	pub paused: RefCell<bool>,
 	pub audio_mixer: AudioMixerRef,
 	pub music_name: String,
-	pub music_sound: Option<SoundRef>
+	pub music_sound: Option<SoundRef>,
+	pub capture_path: Option<String>,
+	pub screenshot_key: Option<Keycode>,
+	pub record_key: Option<Keycode>,
+	pub recording_format: RecordingFormat,
+	pub recording_frame_interval: usize,
+	pub screenshot_requested: RefCell<bool>,
+	pub recording: RefCell<Option<Recording>>,
+	pub capture_error: RefCell<Option<io::Error>>,
+	pub upscale_filter: RefCell<UpscaleFilter>,
+	pub display_settings: RefCell<DisplaySettings>,
+	pub display_settings_error: RefCell<Option<io::Error>>
 }
 
 pub struct FramePace {
@@ -180,15 +244,130 @@ pub struct RenderState {
 	resolution_target: ResolutionTarget,
 	dest_size: RenderSize,
 	window_dest_size: RenderSize,
-	render_buf: Vec<Vec<u32>>,
+	render_buf: FrameBuffer,
+	filter_buf: FrameBuffer,
 	texture: Texture,
+	texture_width: usize,
+	texture_height: usize,
+	display_settings: DisplaySettings,
 	frame_pace: FramePace,
 	_audio: AudioDevice<AudioMixerCallback>
 }
 
+fn display_error<E: fmt::Display>(message: &str, err: E) -> io::Error {
+	io::Error::other(format!("{}: {}", message, err))
+}
+
+impl RenderState {
+	// Recomputes render sizes after the window changes size, recreating the texture and buffers
+	fn update_render_size(&mut self, game_state: &mut GameState) {
+		let window_size = self.canvas.window().size();
+		let draw_size = self.canvas.window().drawable_size();
+		self.screen_width = draw_size.0 as usize;
+		self.screen_height = draw_size.1 as usize;
+		self.window_width = window_size.0 as usize;
+		self.window_height = window_size.1 as usize;
+		let (render_size, dest_size) = self.resolution_target.compute_render_sizes(
+			self.screen_width, self.screen_height);
+
+		game_state.render_size = render_size;
+		self.window_dest_size = RenderSize {
+			width: (dest_size.width * self.window_width) / self.screen_width,
+			height: (dest_size.height * self.window_height) / self.screen_height
+		};
+		self.dest_size = dest_size;
+
+		self.texture = self.canvas.create_texture_streaming(PixelFormatEnum::RGB888,
+			game_state.render_size.width as u32, game_state.render_size.height as u32).unwrap();
+		self.texture_width = game_state.render_size.width;
+		self.texture_height = game_state.render_size.height;
+
+		self.render_buf.resize(game_state.render_size.width, game_state.render_size.height);
+	}
+
+	// SDL can only change vsync by creating a new renderer. The window is taken out of the current
+	// canvas by swapping in a canvas for a hidden placeholder window. Textures are destroyed along
+	// with the old renderer and are recreated by the caller.
+	fn recreate_canvas(&mut self, vsync: bool) -> Result<(), io::Error> {
+		let placeholder = self.canvas.window().subsystem().window("", 1, 1).hidden().build()
+			.map_err(|err| display_error("Failed to create placeholder window", err))?
+			.into_canvas().build()
+			.map_err(|err| display_error("Failed to create placeholder renderer", err))?;
+		let window = mem::replace(&mut self.canvas, placeholder).into_window();
+
+		// Hold on to the window context, as the window is dropped if creating the renderer fails
+		let context = window.context();
+		let result = if vsync {
+			window.into_canvas().accelerated().present_vsync().build()
+		} else {
+			window.into_canvas().accelerated().build()
+		};
+		match result {
+			Ok(canvas) => {
+				self.canvas = canvas;
+				Ok(())
+			},
+			Err(err) => {
+				// Fall back to a renderer with default flags on the same window. The context is the only
+				// remaining reference to the window, so the new handle takes ownership of it.
+				let window = unsafe { Window::from_ref(context) };
+				self.canvas = window.into_canvas().build()
+					.map_err(|err| display_error("Failed to create renderer", err))?;
+				Err(display_error("Failed to change vsync", err))
+			}
+		}
+	}
+
+	fn apply_display_settings(&mut self, game_state: &mut GameState) {
+		let settings = game_state.get_display_settings();
+		if settings == self.display_settings {
+			return;
+		}
+
+		if settings.vsync != self.display_settings.vsync {
+			if let Err(err) = self.recreate_canvas(settings.vsync) {
+				*game_state.display_settings_error.borrow_mut() = Some(err);
+			}
+		}
+
+		if settings.window_mode != self.display_settings.window_mode {
+			let window = self.canvas.window_mut();
+			let result = match settings.window_mode {
+				WindowMode::Windowed => {
+					window.set_bordered(true);
+					window.set_fullscreen(FullscreenType::Off)
+				},
+				WindowMode::Borderless => window.set_fullscreen(FullscreenType::Desktop),
+				WindowMode::Fullscreen => window.set_fullscreen(FullscreenType::True)
+			};
+			if let Err(err) = result {
+				*game_state.display_settings_error.borrow_mut() = Some(display_error("Failed to change window mode", err));
+			}
+		}
+
+		self.resolution_target = settings.resolution_target.clone();
+		self.update_render_size(game_state);
+
+		if let (WindowMode::Windowed, Some(scale)) = (&settings.window_mode, settings.window_scale) {
+			// Size the drawable area to a multiple of the render size, converting to window coordinates
+			// for high DPI displays
+			let width = (game_state.render_size.width * scale * self.window_width) / self.screen_width;
+			let height = (game_state.render_size.height * scale * self.window_height) / self.screen_height;
+			if let Err(err) = self.canvas.window_mut().set_size(width as u32, height as u32) {
+				*game_state.display_settings_error.borrow_mut() = Some(display_error("Failed to resize window", err));
+			}
+			self.update_render_size(game_state);
+		}
+
+		self.display_settings = settings;
+	}
+}
+
 pub trait Game {
 	fn init(&mut self, game_state: &mut GameState);
 	fn title(&self) -> String;
+	// Company name used along with the title to locate the user data directory
+	fn company(&self) -> String { String::new() }
 	fn target_resolution(&self) -> ResolutionTarget;
 	fn fade_in_on_start(&self) -> bool { true }
 
@@ -242,10 +421,51 @@ impl GameState {
 		self.actor_loaders.insert(name.to_string(), handler);
 	}
 
+	// Map actors of this type are loaded as trigger zones instead of actors
+	pub fn register_trigger_zone_type(&mut self, name: &str) {
+		if !self.trigger_zone_types.iter().any(|zone_type| zone_type == name) {
+			self.trigger_zone_types.push(name.to_string());
+		}
+	}
+
+	// Removes every trigger zone, reporting the actors inside as leaving it
+	fn clear_trigger_zones(&mut self) {
+		let zones = mem::take(&mut self.trigger_zones);
+		for zone in &zones {
+			exit_trigger_zone(zone, self);
+		}
+	}
+
 	fn load_map_now(&mut self, map: &Map) {
+		self.clear_trigger_zones();
 		self.actors.clear();
-		self.map = Some(map.clone());
+		let mut map = map.clone();
+		if self.record_map_changes {
+			if let Some(changes) = self.map_changes.borrow().get(&map.id) {
+				let mut layer_tiles: HashMap<usize, Vec<(usize, usize, Option<TileRef>)>> = HashMap::new();
+				for change in changes.values() {
+					match change.resolve(&self.assets) {
+						Ok(tile) => layer_tiles.entry(change.layer).or_default().push((change.x, change.y, tile)),
+						Err(err) => {
+							// The change stays recorded, but the map is loaded without it
+							*self.map_changes_error.borrow_mut() = Some(io::Error::new(err.kind(),
+								format!("Recorded change to layer {} at ({}, {}) could not be applied: {}",
+								change.layer, change.x, change.y, err)));
+						}
+					}
+				}
+				for (layer, tiles) in layer_tiles {
+					map.set_tiles(layer, tiles);
+				}
+			}
+		}
+		*self.map.borrow_mut() = Some(map.clone());
+		*self.tile_animations.borrow_mut() = vec![LayerAnimationState::new(); map.layers.len()];
 		for actor in &map.actors {
+			if self.trigger_zone_types.contains(&actor.actor_type) {
+				self.trigger_zones.push(TriggerZoneRef::new(RefCell::new(TriggerZone::from_map_actor(actor))));
+				continue;
+			}
 			if let Some(handler) = self.actor_loaders.get(&actor.actor_type) {
 				if let Some(actor) = handler(actor, &self.assets) {
 					self.actors.push(ActorRef::new(RefCell::new(actor)));
@@ -259,6 +479,9 @@ impl GameState {
 		}
 		self.camera_shake_x = 0;
 		self.camera_shake_y = 0;
+		for viewport in &self.viewports {
+			viewport.borrow_mut().set_map_bounds(map.bounds());
+		}
 	}
 
 	pub fn load_map(&self, map: &Rc<Map>) {
@@ -271,6 +494,96 @@ impl GameState {
 		self.pending_events.borrow_mut().push(PendingEvent::UnloadMap);
 	}
 
+	// Tile edits take effect immediately, for both collision and rendering. The map must not be borrowed
+	// by the caller while editing it.
+	pub fn set_map_tile(&self, layer: usize, x: usize, y: usize, tile: Option<TileRef>) {
+		self.set_map_tiles(layer, vec![(x, y, tile)]);
+	}
+
+	pub fn fill_map_tiles(&self, area: &TileArea, tile: Option<TileRef>) {
+		let mut tiles = Vec::new();
+		for tile_y in area.y..(area.y + area.height) {
+			for tile_x in area.x..(area.x + area.width) {
+				tiles.push((tile_x, tile_y, tile.clone()));
+			}
+		}
+		self.set_map_tiles(area.layer, tiles);
+	}
+
+	// Copies the tiles of `src` so that its top left tile is placed at (`dest_x`, `dest_y`)
+	pub fn copy_map_tiles(&self, src: &TileArea, dest_layer: usize, dest_x: usize, dest_y: usize) {
+		let mut tiles = Vec::new();
+		if let Some(map) = &*self.map.borrow() {
+			if let Some(layer) = map.layers.get(src.layer) {
+				for tile_y in src.y..min(src.y + src.height, layer.height) {
+					for tile_x in src.x..min(src.x + src.width, layer.width) {
+						tiles.push((dest_x + (tile_x - src.x), dest_y + (tile_y - src.y),
+							layer.get_tile(tile_x, tile_y).clone()));
+					}
+				}
+			}
+		}
+		self.set_map_tiles(dest_layer, tiles);
+	}
+
+	// Replaces any number of tiles in a layer of the loaded map at once
+	pub fn set_map_tiles(&self, layer: usize, tiles: Vec<(usize, usize, Option<TileRef>)>) {
+		let mut map = self.map.borrow_mut();
+		let map = match &mut *map {
+			Some(map) => map,
+			None => return
+		};
+		let tiles = map.set_tiles(layer, tiles);
+		if self.record_map_changes {
+			let mut map_changes = self.map_changes.borrow_mut();
+			let changes = map_changes.entry(map.id.clone()).or_default();
+			for (x, y, tile) in &tiles {
+				changes.insert((layer, *x, *y), MapTileChange::new(layer, *x, *y, tile));
+			}
+		}
+	}
+
+	// Returns the error from the last recorded tile change that could not be applied when loading a map,
+	// if any
+	pub fn take_map_changes_error(&self) -> Option<io::Error> {
+		self.map_changes_error.borrow_mut().take()
+	}
+
+	// Recorded tile changes by map id, in a form that can be stored in save games
+	pub fn recorded_map_changes(&self) -> HashMap<String, Vec<MapTileChange>> {
+		self.map_changes.borrow().iter()
+			.map(|(map_id, changes)| (map_id.clone(), changes.values().cloned().collect())).collect()
+	}
+
+	// Adds previously recorded tile changes, such as from a save game, and applies them to the loaded map
+	// if it is one of the changed maps. Changes to other maps are applied when they are loaded, if
+	// recording is enabled.
+	pub fn restore_map_changes(&self, changes: HashMap<String, Vec<MapTileChange>>) -> Result<(), io::Error> {
+		let current_map_id = self.map.borrow().as_ref().map(|map| map.id.clone());
+		for (map_id, map_changes) in changes {
+			if Some(&map_id) == current_map_id.as_ref() {
+				let mut layer_tiles: HashMap<usize, Vec<(usize, usize, Option<TileRef>)>> = HashMap::new();
+				for change in &map_changes {
+					let tile = change.resolve(&self.assets)?;
+					layer_tiles.entry(change.layer).or_default().push((change.x, change.y, tile));
+				}
+				for (layer, tiles) in layer_tiles {
+					self.set_map_tiles(layer, tiles);
+				}
+			} else {
+				for change in &map_changes {
+					change.resolve(&self.assets)?;
+				}
+				let mut recorded = self.map_changes.borrow_mut();
+				let recorded = recorded.entry(map_id).or_default();
+				for change in map_changes {
+					recorded.insert((change.layer, change.x, change.y), change);
+				}
+			}
+		}
+		Ok(())
+	}
+
 	pub fn fade_out(&self) {
 		self.pending_events.borrow_mut().push(PendingEvent::FadeOut);
 	}
@@ -327,6 +640,43 @@ impl GameState {
 		}));
 	}
 
+	pub fn add_viewport(&self, viewport: Viewport) -> ViewportRef {
+		let viewport_ref = ViewportRef::new(RefCell::new(viewport));
+		self.pending_events.borrow_mut().push(PendingEvent::AddViewport(AddViewportEvent {
+			viewport: viewport_ref.clone()
+		}));
+		viewport_ref
+	}
+
+	pub fn remove_viewport(&self, viewport: &ViewportRef) {
+		self.pending_events.borrow_mut().push(PendingEvent::RemoveViewport(RemoveViewportEvent {
+			viewport: viewport.clone()
+		}));
+	}
+
+	pub fn clear_viewports(&self) {
+		self.pending_events.borrow_mut().push(PendingEvent::ClearViewports);
+	}
+
+	// Zones are removed when a new map is loaded, along with the map's actors
+	pub fn add_trigger_zone(&self, zone: TriggerZone) -> TriggerZoneRef {
+		let zone_ref = TriggerZoneRef::new(RefCell::new(zone));
+		self.pending_events.borrow_mut().push(PendingEvent::AddTriggerZone(AddTriggerZoneEvent {
+			zone: zone_ref.clone()
+		}));
+		zone_ref
+	}
+
+	pub fn remove_trigger_zone(&self, zone: &TriggerZoneRef) {
+		self.pending_events.borrow_mut().push(PendingEvent::RemoveTriggerZone(RemoveTriggerZoneEvent {
+			zone: zone.clone()
+		}));
+	}
+
+	pub fn trigger_zones_at(&self, rect: &BoundingRect) -> Vec<TriggerZoneRef> {
+		self.trigger_zones.iter().filter(|zone| zone.borrow().bounds.is_colliding(rect)).cloned().collect()
+	}
+
 	pub fn set_camera(&self, camera: Option<Camera>) {
 		self.pending_events.borrow_mut().push(PendingEvent::SetCamera(SetCameraEvent {
 			camera
@@ -345,6 +695,207 @@ impl GameState {
 		}));
 	}
 
+	pub fn invalidate_layer_caches(&self) {
+		for cache in self.layer_caches.borrow_mut().iter_mut() {
+			cache.invalidate();
+		}
+		for viewport in &self.viewports {
+			viewport.borrow_mut().invalidate_layer_caches();
+		}
+	}
+
+	// Moves an actor in the collision broadphase. Needed after moving an actor other than during its own
+	// tick, so that it is found at its new position by queries made in the same frame.
+	pub fn update_actor_bounds(&self, actor: &ActorRef, bounds: Option<BoundingRect>) {
+		self.spatial_grid.borrow_mut().update(actor, bounds);
+	}
+
+	pub fn actors_in_rect(&self, rect: &BoundingRect, channel: Option<u32>) -> Vec<ActorRef> {
+		self.spatial_grid.borrow().actors_in_rect(rect, channel)
+	}
+
+	pub fn actors_in_radius(&self, x: isize, y: isize, radius: isize, channel: Option<u32>) -> Vec<ActorRef> {
+		self.spatial_grid.borrow().actors_in_radius(x, y, radius, channel)
+	}
+
+	pub fn raycast_actors(&self, x0: isize, y0: isize, x1: isize, y1: isize, channel: Option<u32>) -> Option<ActorRaycastHit> {
+		self.spatial_grid.borrow().raycast(x0, y0, x1, y1, channel)
+	}
+
+	pub fn pause_tile_animation(&self, layer: usize) {
+		if let Some(animation) = self.tile_animations.borrow_mut().get_mut(layer) {
+			animation.pause();
+		}
+	}
+
+	pub fn resume_tile_animation(&self, layer: usize) {
+		if let Some(animation) = self.tile_animations.borrow_mut().get_mut(layer) {
+			animation.resume();
+		}
+	}
+
+	pub fn reset_tile_animation(&self, layer: usize) {
+		if let Some(animation) = self.tile_animations.borrow_mut().get_mut(layer) {
+			animation.reset();
+		}
+	}
+
+	pub fn is_tile_animation_paused(&self, layer: usize) -> bool {
+		match self.tile_animations.borrow().get(layer) {
+			Some(animation) => animation.paused,
+			None => false
+		}
+	}
+
+	// Plays the triggered tile animation at a tile position in a layer
+	pub fn trigger_tile_animation(&self, layer: usize, x: usize, y: usize) -> bool {
+		if let Some(map) = &*self.map.borrow() {
+			if let (Some(map_layer), Some(animation)) = (map.layers.get(layer),
+				self.tile_animations.borrow_mut().get_mut(layer)) {
+				return animation.trigger(map_layer, x, y);
+			}
+		}
+		false
+	}
+
+	// Plays the triggered tile animations of all tiles overlapping a rectangle in map coordinates, such
+	// as the tiles an actor is standing on. Returns the number of tiles that started playing.
+	pub fn trigger_tile_animations_in_rect(&self, rect: &BoundingRect) -> usize {
+		let mut count = 0;
+		if let Some(map) = &*self.map.borrow() {
+			let mut animations = self.tile_animations.borrow_mut();
+			for (layer, animation) in map.layers.iter().zip(animations.iter_mut()) {
+				if layer.effect {
+					continue;
+				}
+				for (x, y) in layer.tiles_in_rect(rect) {
+					if animation.trigger(layer, x, y) {
+						count += 1;
+					}
+				}
+			}
+		}
+		count
+	}
+
+	pub fn get_display_settings(&self) -> DisplaySettings {
+		self.display_settings.borrow().clone()
+	}
+
+	// Display settings are applied before rendering the next frame
+	pub fn set_display_settings(&self, settings: DisplaySettings) {
+		*self.display_settings.borrow_mut() = settings;
+	}
+
+	// Returns the error from the last display settings change that could not be fully applied, if any
+	pub fn take_display_settings_error(&self) -> Option<io::Error> {
+		self.display_settings_error.borrow_mut().take()
+	}
+
+	pub fn set_resolution_target(&self, target: ResolutionTarget) {
+		self.display_settings.borrow_mut().resolution_target = target;
+	}
+
+	pub fn set_window_mode(&self, mode: WindowMode) {
+		self.display_settings.borrow_mut().window_mode = mode;
+	}
+
+	pub fn set_window_scale(&self, scale: Option<usize>) {
+		self.display_settings.borrow_mut().window_scale = scale;
+	}
+
+	pub fn set_vsync(&self, vsync: bool) {
+		self.display_settings.borrow_mut().vsync = vsync;
+	}
+
+	pub fn set_upscale_filter(&self, filter: UpscaleFilter) {
+		*self.upscale_filter.borrow_mut() = filter;
+	}
+
+	pub fn get_upscale_filter(&self) -> UpscaleFilter {
+		self.upscale_filter.borrow().clone()
+	}
+
+	// Captures are written into the given directory. Defaults to a `captures` subdirectory of
+	// `user_data_path`.
+	pub fn set_capture_path(&mut self, path: &str) {
+		self.capture_path = Some(path.to_string());
+	}
+
+	pub fn bind_screenshot_key(&mut self, key: Keycode) {
+		self.screenshot_key = Some(key);
+	}
+
+	pub fn bind_record_key(&mut self, key: Keycode) {
+		self.record_key = Some(key);
+	}
+
+	pub fn take_screenshot(&self) {
+		*self.screenshot_requested.borrow_mut() = true;
+	}
+
+	pub fn start_recording(&self) -> Result<(), io::Error> {
+		if self.is_recording() {
+			return Ok(());
+		}
+		let path = match &self.capture_path {
+			Some(path) => path,
+			None => return Err(io::Error::new(io::ErrorKind::NotFound, "Capture path not set"))
+		};
+		let recording = Recording::start(path, self.recording_format.clone(), self.recording_frame_interval)?;
+		*self.recording.borrow_mut() = Some(recording);
+		Ok(())
+	}
+
+	// Stops recording, returning the path of the completed recording. Errors while finishing it are
+	// reported through `take_capture_error`.
+	pub fn stop_recording(&self) -> Option<String> {
+		let recording = self.recording.borrow_mut().take()?;
+		match recording.finish() {
+			Ok(path) => Some(path),
+			Err(err) => {
+				*self.capture_error.borrow_mut() = Some(err);
+				None
+			}
+		}
+	}
+
+	pub fn is_recording(&self) -> bool {
+		self.recording.borrow().is_some()
+	}
+
+	pub fn is_capturing(&self) -> bool {
+		*self.screenshot_requested.borrow() || self.is_recording()
+	}
+
+	// Returns the last error from saving a screenshot or recording, if any. Recording stops when a
+	// frame fails to save.
+	pub fn take_capture_error(&self) -> Option<io::Error> {
+		self.capture_error.borrow_mut().take()
+	}
+
+	fn capture_frame(&self, render_buf: &FrameBuffer) {
+		if *self.screenshot_requested.borrow() {
+			*self.screenshot_requested.borrow_mut() = false;
+			let result = match &self.capture_path {
+				Some(path) => capture::save_screenshot(path, render_buf).map(|_| ()),
+				None => Err(io::Error::new(io::ErrorKind::NotFound, "Capture path not set"))
+			};
+			if let Err(err) = result {
+				*self.capture_error.borrow_mut() = Some(err);
+			}
+		}
+
+		let result = match &mut *self.recording.borrow_mut() {
+			Some(recording) => recording.add_frame(render_buf, self.frame),
+			None => Ok(())
+		};
+		if let Err(err) = result {
+			*self.capture_error.borrow_mut() = Some(err);
+			self.stop_recording();
+		}
+	}
+
 	fn get_ui_input_layers(&self) -> Vec<UILayerRef> {
 		// Check layouts from top to bottom for input handling
 		for layout in self.ui_layouts.iter().rev() {
@@ -363,6 +914,19 @@ impl GameState {
 	}
 
 	fn key_down(&self, key: Keycode, key_mod: Mod) {
+		if Some(key) == self.screenshot_key {
+			self.take_screenshot();
+			return;
+		}
+		if Some(key) == self.record_key {
+			if self.is_recording() {
+				self.stop_recording();
+			} else if let Err(err) = self.start_recording() {
+				*self.capture_error.borrow_mut() = Some(err);
+			}
+			return;
+		}
+
 		let ui_input_layers = self.get_ui_input_layers();
 		if ui_input_layers.len() > 0 {
 			// Direct input at active UI handlers
@@ -929,12 +1493,7 @@ fn init(title: &str, target: ResolutionTarget, game: &Box<Game>) -> (GameState,
 		render_size.width as u32, render_size.height as u32).unwrap();
 
 	// Create buffer to hold rendered pixels at internal resolution
-	let mut render_buf: Vec<Vec<u32>> = Vec::new();
-	for _ in 0 .. render_size.height {
-		let mut line = Vec::new();
-		line.resize(render_size.width, 0);
-		render_buf.push(line);
-	}
+	let render_buf = FrameBuffer::new(render_size.width, render_size.height);
 
 	let joystick_subsys = sdl.joystick().unwrap();
 	let mut joystick = None;
@@ -964,14 +1523,17 @@ fn init(title: &str, target: ResolutionTarget, game: &Box<Game>) -> (GameState,
 
 	let game = GameState {
 		assets: AssetNamespace::new(),
-		map: None,
+		map: RefCell::new(None),
 		ui_layouts: Vec::new(),
 		actors: Vec::new(),
 		persistent_actors: Vec::new(),
+		spatial_grid: RefCell::new(SpatialGrid::new(spatial::DEFAULT_CELL_SIZE)),
+		trigger_zones: Vec::new(),
 		controlled_actor: None,
 		camera: None,
 		camera_shake_x: 0,
 		camera_shake_y: 0,
+		viewports: Vec::new(),
 		render_size,
 		scroll_x: 0, scroll_y: 0,
 		fade_alpha: match game.fade_in_on_start() {
@@ -981,6 +1543,16 @@ fn init(title: &str, target: ResolutionTarget, game: &Box<Game>) -> (GameState,
 		target_fade_alpha: 0,
 		frame: 0,
 		rendered_frame: 0,
+		render_time: Duration::new(0, 0),
+		layer_caching: true,
+		record_map_changes: false,
+		map_changes: RefCell::new(HashMap::new()),
+		map_changes_error: RefCell::new(None),
+		layer_caches: RefCell::new(Vec::new()),
+		tile_animations: RefCell::new(Vec::new()),
+		render_threads: 1,
+		depth_sort_mode: DepthSortMode::Priority,
+		render_thread_pool: RefCell::new(None),
 		key_bindings: HashMap::new(),
 		axis_bindings: HashMap::new(),
 		button_bindings: HashMap::new(),
@@ -990,6 +1562,7 @@ fn init(title: &str, target: ResolutionTarget, game: &Box<Game>) -> (GameState,
 		ui_button_bindings: HashMap::new(),
 		ui_hat_bindings: HashMap::new(),
 		actor_loaders: HashMap::new(),
+		trigger_zone_types: Vec::new(),
 		pending_events: RefCell::new(Vec::new()),
 		last_click_frame: None,
 		last_click_button: None,
@@ -1002,7 +1575,18 @@ fn init(title: &str, target: ResolutionTarget, game: &Box<Game>) -> (GameState,
 		paused: RefCell::new(false),
 		audio_mixer: mixer,
 		music_name: String::new(),
-		music_sound: None
+		music_sound: None,
+		capture_path: default_capture_path(&game.company(), title),
+		screenshot_key: None,
+		record_key: None,
+		recording_format: RecordingFormat::Gif,
+		recording_frame_interval: 2,
+		screenshot_requested: RefCell::new(false),
+		recording: RefCell::new(None),
+		capture_error: RefCell::new(None),
+		upscale_filter: RefCell::new(UpscaleFilter::None),
+		display_settings: RefCell::new(DisplaySettings::new(target.clone())),
+		display_settings_error: RefCell::new(None)
 	};
 	let render_state = RenderState {
 		canvas, events, _joystick: joystick,
@@ -1011,6 +1595,10 @@ fn init(title: &str, target: ResolutionTarget, game: &Box<Game>) -> (GameState,
 		resolution_target: target,
 		dest_size, window_dest_size,
 		render_buf, texture,
+		filter_buf: FrameBuffer::new(0, 0),
+		texture_width: game.render_size.width,
+		texture_height: game.render_size.height,
+		display_settings: game.get_display_settings(),
 		frame_pace: FramePace {
 			last_frame_instant: Instant::now(),
 			frame_pace_error_ns: 0,
@@ -1025,14 +1613,17 @@ fn init(title: &str, target: ResolutionTarget, game: &Box<Game>) -> (GameState,
 fn init_headless(game: &Box<Game>) -> (GameState, FramePace) {
 	let game_state = GameState {
 		assets: AssetNamespace::new(),
-		map: None,
+		map: RefCell::new(None),
 		ui_layouts: Vec::new(),
 		actors: Vec::new(),
 		persistent_actors: Vec::new(),
+		spatial_grid: RefCell::new(SpatialGrid::new(spatial::DEFAULT_CELL_SIZE)),
+		trigger_zones: Vec::new(),
 		controlled_actor: None,
 		camera: None,
 		camera_shake_x: 0,
 		camera_shake_y: 0,
+		viewports: Vec::new(),
 		render_size: RenderSize { width: 320, height: 240 },
 		scroll_x: 0, scroll_y: 0,
 		fade_alpha: match game.fade_in_on_start() {
@@ -1042,6 +1633,16 @@ fn init_headless(game: &Box<Game>) -> (GameState, FramePace) {
 		target_fade_alpha: 0,
 		frame: 0,
 		rendered_frame: 0,
+		render_time: Duration::new(0, 0),
+		layer_caching: true,
+		record_map_changes: false,
+		map_changes: RefCell::new(HashMap::new()),
+		map_changes_error: RefCell::new(None),
+		layer_caches: RefCell::new(Vec::new()),
+		tile_animations: RefCell::new(Vec::new()),
+		render_threads: 1,
+		depth_sort_mode: DepthSortMode::Priority,
+		render_thread_pool: RefCell::new(None),
 		key_bindings: HashMap::new(),
 		axis_bindings: HashMap::new(),
 		button_bindings: HashMap::new(),
@@ -1051,6 +1652,7 @@ fn init_headless(game: &Box<Game>) -> (GameState, FramePace) {
 		ui_button_bindings: HashMap::new(),
 		ui_hat_bindings: HashMap::new(),
 		actor_loaders: HashMap::new(),
+		trigger_zone_types: Vec::new(),
 		pending_events: RefCell::new(Vec::new()),
 		last_click_frame: None,
 		last_click_button: None,
@@ -1063,7 +1665,18 @@ fn init_headless(game: &Box<Game>) -> (GameState, FramePace) {
 		paused: RefCell::new(false),
 		audio_mixer: AudioMixer::new(),
 		music_name: String::new(),
-		music_sound: None
+		music_sound: None,
+		capture_path: default_capture_path(&game.company(), &game.title()),
+		screenshot_key: None,
+		record_key: None,
+		recording_format: RecordingFormat::Gif,
+		recording_frame_interval: 2,
+		screenshot_requested: RefCell::new(false),
+		recording: RefCell::new(None),
+		capture_error: RefCell::new(None),
+		upscale_filter: RefCell::new(UpscaleFilter::None),
+		display_settings: RefCell::new(DisplaySettings::new(game.target_resolution())),
+		display_settings_error: RefCell::new(None)
 	};
 	let frame_pace = FramePace {
 		last_frame_instant: Instant::now(),
@@ -1073,6 +1686,49 @@ fn init_headless(game: &Box<Game>) -> (GameState, FramePace) {
 	(game_state, frame_pace)
 }
 
+// Finds the actors inside each trigger zone and sends enter, stay and exit events. Zones are not borrowed
+// during the callbacks, so actors can modify or remove them.
+fn update_trigger_zones(game_state: &GameState) {
+	for zone_ref in &game_state.trigger_zones {
+		let changes = {
+			let mut zone = zone_ref.borrow_mut();
+			let inside = if zone.enabled {
+				let collision_mask = zone.collision_mask;
+				game_state.actors_in_rect(&zone.bounds, None).into_iter().filter(|actor| {
+					(actor.borrow().actor_info().collision_layer & collision_mask) != 0
+				}).collect()
+			} else {
+				Vec::new()
+			};
+			zone.update(inside)
+		};
+
+		for actor in &changes.exited {
+			let mut actor_ref = actor.borrow_mut();
+			if !actor_ref.is_destroyed() {
+				actor_ref.on_trigger_exit(zone_ref, game_state);
+			}
+		}
+		for actor in &changes.entered {
+			actor.borrow_mut().on_trigger_enter(zone_ref, game_state);
+		}
+		for actor in &changes.stayed {
+			actor.borrow_mut().on_trigger_stay(zone_ref, game_state);
+		}
+	}
+}
+
+// Reports every actor inside a zone that is being removed as leaving it
+fn exit_trigger_zone(zone_ref: &TriggerZoneRef, game_state: &GameState) {
+	let changes = zone_ref.borrow_mut().update(Vec::new());
+	for actor in &changes.exited {
+		let mut actor_ref = actor.borrow_mut();
+		if !actor_ref.is_destroyed() {
+			actor_ref.on_trigger_exit(zone_ref, game_state);
+		}
+	}
+}
+
 fn next_game_frame(game: &mut Box<Game>, game_state: &mut GameState, frame_pace: &mut FramePace) {
 	// If frame rate dips, we may need to skip frames to ensure consistent play. Run the actor updates as many
 	// times as needed to catch up.
@@ -1087,6 +1743,7 @@ fn next_game_frame(game: &mut Box<Game>, game_state: &mut GameState, frame_pace:
 					for actor in &game_state.persistent_actors {
 						game_state.actors.push(actor.clone());
 					}
+					game_state.spatial_grid.borrow_mut().rebuild(&game_state.actors);
 					for actor_ref in &game_state.actors {
 						let mut actor = actor_ref.borrow_mut();
 						actor.init(&game_state);
@@ -1096,10 +1753,16 @@ fn next_game_frame(game: &mut Box<Game>, game_state: &mut GameState, frame_pace:
 					}
 				},
 				PendingEvent::UnloadMap => {
+					game_state.clear_trigger_zones();
 					game_state.actors.clear();
-					game_state.map = None;
+					game_state.spatial_grid.borrow_mut().clear();
+					*game_state.map.borrow_mut() = None;
+					game_state.tile_animations.borrow_mut().clear();
 					game_state.camera_shake_x = 0;
 					game_state.camera_shake_y = 0;
+					for viewport in &game_state.viewports {
+						viewport.borrow_mut().set_camera_shake(0, 0);
+					}
 				},
 				PendingEvent::AddActor(add_actor) => {
 					game_state.actors.push(add_actor.actor);
@@ -1129,6 +1792,27 @@ fn next_game_frame(game: &mut Box<Game>, game_state: &mut GameState, frame_pace:
 				PendingEvent::SetControlledActor(controlled_actor) => {
 					game_state.controlled_actor = controlled_actor.actor;
 				},
+				PendingEvent::AddViewport(add_viewport) => {
+					if let Some(map) = &*game_state.map.borrow() {
+						add_viewport.viewport.borrow_mut().set_map_bounds(map.bounds());
+					}
+					game_state.viewports.push(add_viewport.viewport);
+				},
+				PendingEvent::RemoveViewport(remove_viewport) => {
+					game_state.viewports.retain(|viewport| !Rc::ptr_eq(viewport, &remove_viewport.viewport));
+				},
+				PendingEvent::ClearViewports => {
+					game_state.viewports.clear();
+				},
+				PendingEvent::AddTriggerZone(add_zone) => {
+					game_state.trigger_zones.push(add_zone.zone);
+				},
+				PendingEvent::RemoveTriggerZone(remove_zone) => {
+					if game_state.trigger_zones.iter().any(|zone| Rc::ptr_eq(zone, &remove_zone.zone)) {
+						exit_trigger_zone(&remove_zone.zone, game_state);
+					}
+					game_state.trigger_zones.retain(|zone| !Rc::ptr_eq(zone, &remove_zone.zone));
+				},
 				PendingEvent::SetCamera(camera) => {
 					game_state.camera = camera.camera;
 				},
@@ -1158,16 +1842,38 @@ fn next_game_frame(game: &mut Box<Game>, game_state: &mut GameState, frame_pace:
 		game.tick();
 
 		if !game_state.is_paused() {
-			// Advance sprite animations
+			// Advance sprite animations, sending events for frames that start and for animations that finish
+			for actor in &game_state.actors {
+				let mut actor_ref = actor.borrow_mut();
+				let mut events = Vec::new();
+				let mut finished = Vec::new();
+				for sprite in &mut actor_ref.actor_info_mut().sprites {
+					let (sprite_events, sprite_finished) = sprite.advance();
+					events.extend(sprite_events);
+					finished.extend(sprite_finished);
+				}
+
+				for name in &events {
+					actor_ref.on_animation_event(name, game_state);
+				}
+				for name in &finished {
+					actor_ref.on_animation_finished(name, game_state);
+				}
+			}
+
+			// Update the broadphase for collision queries made while actors tick
+			game_state.spatial_grid.borrow_mut().rebuild(&game_state.actors);
+
+			// Tick platforms first, so that the actors they carry move from the platform's new position
 			for actor in &game_state.actors {
 				let mut actor_ref = actor.borrow_mut();
-				let actor_info = actor_ref.actor_info_mut();
-				for sprite in &mut actor_info.sprites {
-					sprite.animation_frame += 1;
+				if actor_ref.actor_info().platform && !actor_ref.is_destroyed() {
+					actor_ref.tick(game_state);
+					game_state.update_actor_bounds(actor, actor_ref.actor_info().world_collision_bounds());
 				}
 			}
 
-			// Tick actors, and keep a list of non-destroyed actors to replace the
+			// Tick the remaining actors, and keep a list of non-destroyed actors to replace the
 			// actor list with afterwards
 			let mut new_actor_list: Vec<ActorRef> = Vec::new();
 			for actor in &game_state.actors {
@@ -1176,18 +1882,26 @@ fn next_game_frame(game: &mut Box<Game>, game_state: &mut GameState, frame_pace:
 					continue;
 				}
 				new_actor_list.push(actor.clone());
-				actor_ref.tick(game_state);
+				if !actor_ref.actor_info().platform {
+					actor_ref.tick(game_state);
+					game_state.update_actor_bounds(actor, actor_ref.actor_info().world_collision_bounds());
+				}
 			}
 
 			// Replace actor list with destroyed actors removed
 			game_state.actors = new_actor_list;
 
+			update_trigger_zones(game_state);
+
 			// Update camera state
 			if let Some(camera) = &mut game_state.camera {
 				camera.tick(&game_state.render_size, &mut game_state.scroll_x, &mut game_state.scroll_y);
 				game_state.scroll_x += game_state.camera_shake_x;
 				game_state.scroll_y += game_state.camera_shake_y;
 			}
+			for viewport in &game_state.viewports {
+				viewport.borrow_mut().tick(&game_state.render_size);
+			}
 		}
 
 		// Process fade animation
@@ -1197,13 +1911,22 @@ fn next_game_frame(game: &mut Box<Game>, game_state: &mut GameState, frame_pace:
 			game_state.fade_alpha -= 1;
 		}
 
+		// Advance tile animations, keeping a state for every layer in case layers were added
+		if let Some(map) = &*game_state.map.borrow() {
+			let mut animations = game_state.tile_animations.borrow_mut();
+			animations.resize(map.layers.len(), LayerAnimationState::new());
+			for (layer, animation) in map.layers.iter().zip(animations.iter_mut()) {
+				animation.tick(layer);
+			}
+		}
+
 		game_state.frame += 1;
 	}
 }
 
 fn frame_pacing(frame_pace: &mut FramePace) {
 	let now = Instant::now();
-	let frame_duration = now.duration_since(frame_pace.last_frame_instant.clone());
+	let frame_duration = now.duration_since(frame_pace.last_frame_instant);
 	let mut frame_ns = frame_pace.frame_pace_error_ns +
 		if frame_duration.as_secs() == 0 { frame_duration.subsec_nanos() as i64 } else { 1_000_000_000 } -
 		(frame_pace.frame_skip_count as i64 * 16_666_666);
@@ -1231,6 +1954,7 @@ fn next_frame(game: &mut Box<Game>, game_state: &mut GameState, render_state: &m
 		}
 	}
 
+	let mut size_changed = false;
 	for event in render_state.events.poll_iter() {
 		match event {
 			Event::Quit {..} => process::exit(0),
@@ -1264,50 +1988,43 @@ fn next_frame(game: &mut Box<Game>, game_state: &mut GameState, render_state: &m
 			Event::TextInput {text, ..} =>
 				game_state.text_input(&text),
 
-			Event::Window {win_event: WindowEvent::SizeChanged(width, height), ..} => {
-				let draw_size = render_state.canvas.window().drawable_size();
-				render_state.screen_width = draw_size.0 as usize;
-				render_state.screen_height = draw_size.1 as usize;
-				render_state.window_width = width as usize;
-				render_state.window_height = height as usize;
-				let (render_size, dest_size) = render_state.resolution_target.compute_render_sizes(
-					render_state.screen_width, render_state.screen_height);
-
-				game_state.render_size = render_size;
-				render_state.window_dest_size = RenderSize {
-					width: (dest_size.width * render_state.window_width) / render_state.screen_width,
-					height: (dest_size.height * render_state.window_height) / render_state.screen_height
-				};
-				render_state.dest_size = dest_size;
-
-				render_state.texture = render_state.canvas.create_texture_streaming(PixelFormatEnum::RGB888,
-					game_state.render_size.width as u32, game_state.render_size.height as u32).unwrap();
-
-				render_state.render_buf = Vec::new();
-				for _ in 0 .. game_state.render_size.height {
-					let mut line = Vec::new();
-					line.resize(game_state.render_size.width, 0);
-					render_state.render_buf.push(line);
-				}
-			},
+			Event::Window {win_event: WindowEvent::SizeChanged(..), ..} =>
+				size_changed = true,
 
 			_ => {}
 		}
 	}
 
+	if size_changed {
+		render_state.update_render_size(game_state);
+	}
+
 	next_game_frame(game, game_state, &mut render_state.frame_pace);
+	render_state.apply_display_settings(game_state);
 
 	// Render game at internal resolution
-	render::render_frame(&game_state.render_size, &mut render_state.render_buf, &game_state);
-
-	// Copy rendered frame into SDL texture
-	let render_buf = &render_state.render_buf;
-	render_state.texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-		for y in 0..game_state.render_size.height {
-			let dest_line = &mut buffer[y * pitch .. (y * pitch) + (game_state.render_size.width * 4)];
-			let src_line = &render_buf[y];
-			LittleEndian::write_u32_into(src_line, dest_line);
+	let render_start = Instant::now();
+	render::render_frame(&mut render_state.render_buf, &game_state);
+	game_state.render_time = render_start.elapsed();
+	game_state.capture_frame(&render_state.render_buf);
+
+	// Apply upscaling filter, then copy frame into SDL texture
+	let filter = game_state.get_upscale_filter();
+	let upload_buf = match filter {
+		UpscaleFilter::None => &render_state.render_buf,
+		_ => {
+			filter.apply(&render_state.render_buf, &mut render_state.filter_buf, &render_state.dest_size);
+			&render_state.filter_buf
 		}
+	};
+	if (upload_buf.width != render_state.texture_width) || (upload_buf.height != render_state.texture_height) {
+		render_state.texture = render_state.canvas.create_texture_streaming(PixelFormatEnum::RGB888,
+			upload_buf.width as u32, upload_buf.height as u32).unwrap();
+		render_state.texture_width = upload_buf.width;
+		render_state.texture_height = upload_buf.height;
+	}
+	render_state.texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+		upload_buf.copy_to_bytes(buffer, pitch);
 	}).unwrap();
 
 	// Present frame scaled to fit screen
@@ -1333,8 +2050,16 @@ fn next_frame(game: &mut Box<Game>, game_state: &mut GameState, render_state: &m
 }
 
 #[cfg(not(target_os = "emscripten"))]
-fn next_frame_headless(game: &mut Box<Game>, game_state: &mut GameState, frame_pace: &mut FramePace) {
+fn next_frame_headless(game: &mut Box<Game>, game_state: &mut GameState, frame_pace: &mut FramePace,
+	render_buf: &mut FrameBuffer) {
 	next_game_frame(game, game_state, frame_pace);
+
+	// Frames are only rendered when headless if they are being captured
+	if game_state.is_capturing() {
+		render_buf.resize(game_state.render_size.width, game_state.render_size.height);
+		render::render_frame(render_buf, game_state);
+		game_state.capture_frame(render_buf);
+	}
 	frame_pacing(frame_pace);
 	game_state.rendered_frame += 1;
 }
@@ -1351,6 +2076,10 @@ pub fn user_data_path(company: &str, title: &str) -> Option<String> {
 	};
 }
 
+fn default_capture_path(company: &str, title: &str) -> Option<String> {
+	user_data_path(company, title).map(|path| Path::new(&path).join("captures").to_string_lossy().into_owned())
+}
+
 pub fn commit_filesystem_changes() {
 	#[cfg(target_os = "emscripten")]
 	{
@@ -1431,6 +2160,137 @@ pub fn run(mut game: Box<Game>) {
 #[cfg(not(target_os = "emscripten"))]
 pub fn run_headless(mut game: Box<Game>) -> GameState {
 	let (mut game_state, mut frame_pace) = init_headless(&game);
+	let mut render_buf = FrameBuffer::new(game_state.render_size.width, game_state.render_size.height);
 	game.init(&mut game_state);
-	loop { next_frame_headless(&mut game, &mut game_state, &mut frame_pace); }
+	loop { next_frame_headless(&mut game, &mut game_state, &mut frame_pace, &mut render_buf); }
+}
+
+#[cfg(test)]
+pub mod tests {
+	use std::cell::{Cell, RefCell};
+	use std::rc::Rc;
+	use actor::{Actor, ActorInfo, BoundingRect};
+	use map::{Map, MapLayer, MapTileChange, TileArea, TileRef};
+	use render::ResolutionTarget;
+	use tile::TileSet;
+	use trigger::{TriggerZone, TriggerZoneRef};
+	use super::{Game, GameState, init_headless, update_trigger_zones};
+
+	struct TestGame;
+
+	impl Game for TestGame {
+		fn init(&mut self, _game_state: &mut GameState) {}
+		fn title(&self) -> String { "Test".to_string() }
+		fn target_resolution(&self) -> ResolutionTarget { ResolutionTarget::fixed_vertical_resolution(240) }
+	}
+
+	// Game state of a headless game without a loaded map
+	pub fn test_game_state() -> GameState {
+		let game: Box<Game> = Box::new(TestGame);
+		init_headless(&game).0
+	}
+
+	#[test]
+	fn map_edits_apply_immediately_and_are_recorded() {
+		let mut game_state = test_game_state();
+		game_state.record_map_changes = true;
+		let mut layer = MapLayer::new("main", 8, 8, 16, 16, 8);
+		let solid = TileRef::new(&Rc::new(TileSet::solid_test_tile_set(16, 16)), 0);
+		for y in 0..8 {
+			for x in 0..8 {
+				layer.set_tile(x, y, Some(solid.clone()));
+			}
+		}
+		let mut map = Map::new("test");
+		map.layers.push(Rc::new(layer));
+		let asset_layer = map.layers[0].clone();
+		game_state.load_map_now(&map);
+
+		let door = BoundingRect { x: 32, y: 32, width: 16, height: 16 };
+		assert!(game_state.map.borrow().as_ref().unwrap().check_collision(&door, 0));
+		game_state.set_map_tile(0, 2, 2, None);
+		assert!(!game_state.map.borrow().as_ref().unwrap().check_collision(&door, 0));
+
+		// The loaded map's copy of the layer is edited, not the asset
+		assert!(asset_layer.get_tile(2, 2).is_some());
+
+		let area = TileArea { layer: 0, x: 4, y: 4, width: 2, height: 2 };
+		game_state.fill_map_tiles(&area, None);
+		game_state.copy_map_tiles(&area, 0, 0, 0);
+		game_state.set_map_tile(0, 4, 4, None);
+		let changes = game_state.recorded_map_changes();
+		assert_eq!(changes[&map.id].len(), 9);
+
+		// Recorded changes are applied again when the map is loaded
+		game_state.load_map_now(&map);
+		let loaded = game_state.map.borrow();
+		let loaded_layer = &loaded.as_ref().unwrap().layers[0];
+		assert!(loaded_layer.get_tile(2, 2).is_none());
+		assert!(loaded_layer.get_tile(5, 5).is_none());
+		assert!(loaded_layer.get_tile(1, 1).is_none());
+		assert!(loaded_layer.get_tile(3, 3).is_some());
+	}
+
+	#[test]
+	fn unresolvable_map_changes_are_reported() {
+		let mut game_state = test_game_state();
+		game_state.record_map_changes = true;
+		let mut map = Map::new("test");
+		map.layers.push(Rc::new(MapLayer::new("main", 8, 8, 16, 16, 8)));
+		let change = MapTileChange {
+			layer: 0,
+			x: 1,
+			y: 1,
+			tile: Some(("missing".to_string(), 0))
+		};
+		game_state.map_changes.borrow_mut().entry(map.id.clone()).or_default().insert((0, 1, 1), change);
+
+		game_state.load_map_now(&map);
+		assert!(game_state.take_map_changes_error().is_some());
+		assert_eq!(game_state.recorded_map_changes()[&map.id].len(), 1);
+	}
+
+	// Actor that counts trigger zone entries and exits
+	struct ZoneVisitor {
+		info: ActorInfo,
+		inside: Rc<Cell<isize>>
+	}
+
+	impl Actor for ZoneVisitor {
+		fn actor_info(&self) -> &ActorInfo {
+			&self.info
+		}
+
+		fn actor_info_mut(&mut self) -> &mut ActorInfo {
+			&mut self.info
+		}
+
+		fn on_trigger_enter(&mut self, _zone: &TriggerZoneRef, _game_state: &GameState) {
+			self.inside.set(self.inside.get() + 1);
+		}
+
+		fn on_trigger_exit(&mut self, _zone: &TriggerZoneRef, _game_state: &GameState) {
+			self.inside.set(self.inside.get() - 1);
+		}
+	}
+
+	#[test]
+	fn clearing_zones_reports_exits() {
+		let mut game_state = test_game_state();
+		let inside = Rc::new(Cell::new(0));
+		let mut info = ActorInfo::new(8, 8);
+		info.collision_bounds = Some(BoundingRect { x: 0, y: 0, width: 16, height: 16 });
+		let actor: Box<Actor> = Box::new(ZoneVisitor { info, inside: inside.clone() });
+		game_state.actors.push(Rc::new(RefCell::new(actor)));
+		game_state.spatial_grid.borrow_mut().rebuild(&game_state.actors);
+		let bounds = BoundingRect { x: 0, y: 0, width: 64, height: 64 };
+		game_state.trigger_zones.push(Rc::new(RefCell::new(TriggerZone::new("first", bounds.clone()))));
+		game_state.trigger_zones.push(Rc::new(RefCell::new(TriggerZone::new("second", bounds))));
+
+		update_trigger_zones(&game_state);
+		assert_eq!(inside.get(), 2);
+		game_state.clear_trigger_zones();
+		assert_eq!(inside.get(), 0);
+		assert!(game_state.trigger_zones.is_empty());
+	}
 }
@@ -2,6 +2,7 @@
 extern crate serde_derive;
 
 pub mod render;
+pub mod filter;
 pub mod palette;
 pub mod tile;
 pub mod sprite;
@@ -11,8 +12,13 @@ pub mod game;
 pub mod ui;
 pub mod actor;
 pub mod camera;
+pub mod viewport;
 pub mod widgets;
 pub mod audio;
+pub mod capture;
+pub mod spatial;
+pub mod trigger;
+pub mod pathfinding;
 
 #[cfg(target_os = "emscripten")]
 pub mod emscripten;
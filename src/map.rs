@@ -3,7 +3,9 @@ extern crate serde_json;
 use std::io;
 use std::rc::Rc;
 use std::cmp::{min, max};
-use tile::{TileSet, PaletteWithOffset};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tile::{TileSet, PaletteWithOffset, TileAnimationMode};
 use asset;
 use asset::AssetNamespace;
 use actor::BoundingRect;
@@ -26,7 +28,9 @@ struct RawMapLayer {
 	pub parallax_x: i16,
 	pub parallax_y: i16,
 	pub auto_scroll_x: i16,
-	pub auto_scroll_y: i16
+	pub auto_scroll_y: i16,
+	pub tint_color: Option<u16>,
+	pub tint_amount: Option<u8>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -60,7 +64,14 @@ pub enum BlendMode {
 	Normal,
 	Add,
 	Subtract,
-	Multiply
+	Multiply,
+	Screen,
+	Overlay,
+	Difference,
+	Lighten,
+	Darken,
+	// Mixes source pixels towards the given color, amount is in 1/16 steps
+	Tint(u32, u8)
 }
 
 #[derive(Clone)]
@@ -86,7 +97,53 @@ pub struct MapLayer {
 	pub parallax_x: i16,
 	pub parallax_y: i16,
 	pub auto_scroll_x: i16,
-	pub auto_scroll_y: i16
+	pub auto_scroll_y: i16,
+	// Identifies the contents of the layer for render caches. Changed by `set_tile` and `resize`, and code
+	// that writes `tiles` directly must call `touch`.
+	pub generation: usize
+}
+
+static NEXT_LAYER_GENERATION: AtomicUsize = AtomicUsize::new(1);
+
+// Playback state of the tile animations in a map layer. Layers are shared between maps, so the state
+// is kept by the game separately from the layer itself.
+#[derive(Clone)]
+pub struct LayerAnimationState {
+	pub paused: bool,
+	pub time: usize,
+	// Time at which triggered tiles started playing, by tile position
+	pub triggers: HashMap<(usize, usize), usize>
+}
+
+// First point along a ray that is inside tile collision, with the tile that contains it
+#[derive(Clone)]
+pub struct RaycastHit {
+	pub x: isize,
+	pub y: isize,
+	pub layer: usize,
+	pub tile_x: usize,
+	pub tile_y: usize,
+	pub tile: TileRef
+}
+
+// Rectangle of tiles within one layer of a map
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileArea {
+	pub layer: usize,
+	pub x: usize,
+	pub y: usize,
+	pub width: usize,
+	pub height: usize
+}
+
+// Tile placed in a map at runtime, in a form that can be stored in save games. The tile set is given
+// by id. Palette overrides are not recorded.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MapTileChange {
+	pub layer: usize,
+	pub x: usize,
+	pub y: usize,
+	pub tile: Option<(String, usize)>
 }
 
 #[derive(Clone)]
@@ -109,6 +166,38 @@ pub struct Map {
 	pub actors: Vec<MapActor>
 }
 
+impl BlendMode {
+	fn from_raw(blend: u32, tint_color: Option<u16>, tint_amount: Option<u8>) -> Result<BlendMode, io::Error> {
+		match blend {
+			0 => Ok(BlendMode::Normal),
+			1 => Ok(BlendMode::Add),
+			2 => Ok(BlendMode::Subtract),
+			3 => Ok(BlendMode::Multiply),
+			4 => Ok(BlendMode::Screen),
+			5 => Ok(BlendMode::Overlay),
+			6 => Ok(BlendMode::Difference),
+			7 => Ok(BlendMode::Lighten),
+			8 => Ok(BlendMode::Darken),
+			9 => {
+				let color = match tint_color {
+					Some(color) => Palette::convert_color(color),
+					None => return Err(io::Error::new(io::ErrorKind::InvalidData, "Tint blend mode without color"))
+				};
+				let amount = tint_amount.unwrap_or(16);
+				if amount > 16 {
+					return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid tint amount"));
+				}
+				Ok(BlendMode::Tint(color, amount))
+			},
+			_ => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid blend mode"))
+		}
+	}
+
+	pub fn tint(color: u16, amount: u8) -> BlendMode {
+		BlendMode::Tint(Palette::convert_color(color), amount)
+	}
+}
+
 impl TileRef {
 	pub fn new(tile_set: &Rc<TileSet>, tile_index: usize) -> TileRef {
 		TileRef {
@@ -128,6 +217,120 @@ impl TileRef {
 			})
 		}
 	}
+
+	pub fn animation_mode(&self) -> &TileAnimationMode {
+		&self.tile_set.tiles[self.tile_index].animation_mode
+	}
+
+	// Animation frame of the tile `t` frames into its layer's animation. Triggered tiles stay on their
+	// first frame until `trigger_time`.
+	pub fn frame_for_time(&self, t: usize, trigger_time: Option<usize>) -> usize {
+		match self.animation_mode() {
+			TileAnimationMode::Trigger => match trigger_time {
+				Some(start) if t >= start => self.tile_set.tile_frame_for_time(self.tile_index, t - start),
+				_ => 0
+			},
+			_ => self.tile_set.tile_frame_for_time(self.tile_index, t)
+		}
+	}
+}
+
+impl MapTileChange {
+	pub fn new(layer: usize, x: usize, y: usize, tile: &Option<TileRef>) -> MapTileChange {
+		MapTileChange {
+			layer, x, y,
+			tile: tile.as_ref().map(|tile_ref| (tile_ref.tile_set.id.clone(), tile_ref.tile_index))
+		}
+	}
+
+	pub fn resolve(&self, assets: &AssetNamespace) -> Result<Option<TileRef>, io::Error> {
+		match &self.tile {
+			Some((tile_set_id, tile_index)) => {
+				let tile_set = match assets.get_tile_set_by_id(tile_set_id) {
+					Some(tile_set) => tile_set,
+					None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+						format!("Tile set {} not found", tile_set_id)))
+				};
+				if *tile_index >= tile_set.tiles.len() {
+					return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid tile index"));
+				}
+				Ok(Some(TileRef::new(&tile_set, *tile_index)))
+			},
+			None => Ok(None)
+		}
+	}
+}
+
+impl Default for LayerAnimationState {
+	fn default() -> LayerAnimationState {
+		LayerAnimationState::new()
+	}
+}
+
+impl LayerAnimationState {
+	pub fn new() -> LayerAnimationState {
+		LayerAnimationState {
+			paused: false,
+			time: 0,
+			triggers: HashMap::new()
+		}
+	}
+
+	pub fn pause(&mut self) {
+		self.paused = true;
+	}
+
+	pub fn resume(&mut self) {
+		self.paused = false;
+	}
+
+	// Restarts all tile animations in the layer from their first frame
+	pub fn reset(&mut self) {
+		self.time = 0;
+		self.triggers.clear();
+	}
+
+	// Starts a triggered tile playing. Returns false if there is no triggered tile at the position or it
+	// is already playing.
+	pub fn trigger(&mut self, layer: &MapLayer, x: usize, y: usize) -> bool {
+		if (x >= layer.width) || (y >= layer.height) || self.triggers.contains_key(&(x, y)) {
+			return false;
+		}
+		match layer.get_tile(x, y) {
+			Some(tile_ref) if (*tile_ref.animation_mode() == TileAnimationMode::Trigger) &&
+				tile_ref.tile_set.tile_animation(tile_ref.tile_index).is_some() => {
+				self.triggers.insert((x, y), self.time);
+				true
+			},
+			_ => false
+		}
+	}
+
+	pub fn frame_for_tile(&self, tile_ref: &TileRef, x: usize, y: usize) -> usize {
+		tile_ref.frame_for_time(self.time, self.triggers.get(&(x, y)).cloned())
+	}
+
+	pub fn tick(&mut self, layer: &MapLayer) {
+		if self.paused {
+			return;
+		}
+		self.time += 1;
+
+		// Triggered tiles that have played through can be triggered again
+		let time = self.time;
+		self.triggers.retain(|&(x, y), start| {
+			if (x >= layer.width) || (y >= layer.height) {
+				return false;
+			}
+			match layer.get_tile(x, y) {
+				Some(tile_ref) => match tile_ref.tile_set.tile_animation(tile_ref.tile_index) {
+					Some(animation) => (time - *start) < animation.total_length,
+					None => false
+				},
+				None => false
+			}
+		});
+	}
 }
 
 impl MapLayer {
@@ -143,7 +346,8 @@ impl MapLayer {
 			parallax_x: 0x100,
 			parallax_y: 0x100,
 			auto_scroll_x: 0,
-			auto_scroll_y: 0
+			auto_scroll_y: 0,
+			generation: MapLayer::next_generation()
 		};
 		layer.tiles.resize(width * height, None);
 
@@ -161,18 +365,14 @@ impl MapLayer {
 			tile_depth: raw_map_layer.tile_depth,
 			tiles: Vec::new(),
 			effect: raw_map_layer.effect,
-			blend_mode: match raw_map_layer.blend {
-				0 => BlendMode::Normal,
-				1 => BlendMode::Add,
-				2 => BlendMode::Subtract,
-				3 => BlendMode::Multiply,
-				_ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid blend mode"))
-			},
+			blend_mode: BlendMode::from_raw(raw_map_layer.blend, raw_map_layer.tint_color,
+				raw_map_layer.tint_amount)?,
 			alpha: raw_map_layer.alpha,
 			parallax_x: raw_map_layer.parallax_x,
 			parallax_y: raw_map_layer.parallax_y,
 			auto_scroll_x: raw_map_layer.auto_scroll_x,
-			auto_scroll_y: raw_map_layer.auto_scroll_y
+			auto_scroll_y: raw_map_layer.auto_scroll_y,
+			generation: MapLayer::next_generation()
 		};
 
 		// Check effect layer flag for asset import type
@@ -258,6 +458,16 @@ impl MapLayer {
 
 	pub fn set_tile(&mut self, x: usize, y: usize, tile: Option<TileRef>) {
 		self.tiles[(y * self.width) + x] = tile;
+		self.touch();
+	}
+
+	fn next_generation() -> usize {
+		NEXT_LAYER_GENERATION.fetch_add(1, Ordering::Relaxed)
+	}
+
+	// Marks the tiles as changed, so that render caches of the layer are rebuilt
+	pub fn touch(&mut self) {
+		self.generation = MapLayer::next_generation();
 	}
 
 	pub fn resize(&mut self, width: usize, height: usize) {
@@ -281,6 +491,51 @@ impl MapLayer {
 		self.width = width;
 		self.height = height;
 		self.tiles = new_tiles;
+		self.touch();
+	}
+
+	// Properties of the tile at a point in map coordinates, if there is a tile there
+	pub fn tile_properties_at(&self, x: isize, y: isize) -> Option<&HashMap<String, serde_json::Value>> {
+		if (x < 0) || (y < 0) || (self.tile_width == 0) || (self.tile_height == 0) {
+			return None;
+		}
+		let tile_x = x as usize / self.tile_width;
+		let tile_y = y as usize / self.tile_height;
+		if (tile_x >= self.width) || (tile_y >= self.height) {
+			return None;
+		}
+		match self.get_tile(tile_x, tile_y) {
+			Some(tile_ref) => Some(&tile_ref.tile_set.tiles[tile_ref.tile_index].properties),
+			None => None
+		}
+	}
+
+	// Checks a single point against the collision of the tile containing it. Unlike `check_collision`,
+	// points outside the layer are not colliding.
+	pub fn check_point_collision(&self, x: isize, y: isize, channel: u32) -> bool {
+		if (x < 0) || (y < 0) || (self.tile_width == 0) || (self.tile_height == 0) ||
+			(x >= (self.width * self.tile_width) as isize) || (y >= (self.height * self.tile_height) as isize) {
+			return false;
+		}
+		self.check_collision(&BoundingRect { x, y, width: 1, height: 1 }, channel)
+	}
+
+	// Tile positions in the layer overlapping a rectangle in map coordinates
+	pub fn tiles_in_rect(&self, rect: &BoundingRect) -> Vec<(usize, usize)> {
+		let mut tiles = Vec::new();
+		if (rect.width <= 0) || (rect.height <= 0) || (self.tile_width == 0) || (self.tile_height == 0) {
+			return tiles;
+		}
+		let left_tile = max(rect.x.div_euclid(self.tile_width as isize), 0);
+		let right_tile = min((rect.x + rect.width - 1).div_euclid(self.tile_width as isize), self.width as isize - 1);
+		let top_tile = max(rect.y.div_euclid(self.tile_height as isize), 0);
+		let bottom_tile = min((rect.y + rect.height - 1).div_euclid(self.tile_height as isize), self.height as isize - 1);
+		for tile_y in top_tile ..= bottom_tile {
+			for tile_x in left_tile ..= right_tile {
+				tiles.push((tile_x as usize, tile_y as usize));
+			}
+		}
+		tiles
 	}
 
 	pub fn check_collision(&self, rect: &BoundingRect, channel: u32) -> bool {
@@ -323,6 +578,16 @@ impl MapLayer {
 							}
 						}
 					}
+					for slope in tile.slopes_for_channel(channel) {
+						let tile_top = tile_y * self.tile_height as isize;
+						if let Some(surface) = slope.highest_surface(tile_x * self.tile_width as isize, tile_top,
+							rect.x, rect.x + rect.width) {
+							let slope_bottom = tile_top + slope.bounds.y + slope.bounds.height;
+							if (surface < (rect.y + rect.height)) && (rect.y < slope_bottom) {
+								return true;
+							}
+						}
+					}
 				}
 			}
 		}
@@ -430,6 +695,35 @@ impl MapLayer {
 							}
 						}
 					}
+
+					// Slopes block from the side at their edges, below the surface height there. The part of
+					// an edge within the distance moved of the actor's feet is left open, so that actors can
+					// walk from one slope onto the next and then be moved up onto it by `slope_surface`.
+					let tile_left = tile_x * self.tile_width as isize;
+					let tile_top = tile_y * self.tile_height as isize;
+					let step = (final_x - rect.x).abs();
+					for slope in tile.slopes_for_channel(channel) {
+						let slope_left = tile_left + slope.bounds.x;
+						let slope_right = slope_left + slope.bounds.width;
+						let slope_bottom = tile_top + slope.bounds.y + slope.bounds.height;
+						if ((rect.x + rect.width) <= slope_left) && (final_x > rect.x) {
+							let edge_top = slope_bottom - slope.left_height;
+							if (edge_top < (rect.y + rect.height - step)) && (rect.y < slope_bottom) &&
+								((slope_left - rect.width) < revised_x) {
+								// Found earlier collision moving to the right
+								revised_x = slope_left - rect.width;
+								collision = Some(revised_x);
+							}
+						} else if (rect.x >= slope_right) && (final_x < rect.x) {
+							let edge_top = slope_bottom - slope.right_height;
+							if (edge_top < (rect.y + rect.height - step)) && (rect.y < slope_bottom) &&
+								(slope_right > revised_x) {
+								// Found earlier collision moving to the left
+								revised_x = slope_right;
+								collision = Some(revised_x);
+							}
+						}
+					}
 				}
 			}
 		}
@@ -537,12 +831,76 @@ impl MapLayer {
 							}
 						}
 					}
+					let tile_left = tile_x * self.tile_width as isize;
+					let tile_top = tile_y * self.tile_height as isize;
+					if final_y > rect.y {
+						// One-way solids and slopes only stop downward movement that starts above them
+						for tile_rect in tile.one_way_for_channel(channel) {
+							let check_x = tile_left + tile_rect.x;
+							let check_y = tile_top + tile_rect.y;
+							if (check_x >= (rect.x + rect.width)) || (rect.x >= (check_x + tile_rect.width)) {
+								continue;
+							}
+							if ((rect.y + rect.height) <= check_y) && ((check_y - rect.height) < revised_y) {
+								revised_y = check_y - rect.height;
+								collision = Some(revised_y);
+							}
+						}
+						for slope in tile.slopes_for_channel(channel) {
+							if let Some(surface) = slope.highest_surface(tile_left, tile_top, rect.x, rect.x + rect.width) {
+								if ((rect.y + rect.height) <= surface) && ((surface - rect.height) < revised_y) {
+									revised_y = surface - rect.height;
+									collision = Some(revised_y);
+								}
+							}
+						}
+					} else {
+						// The solid part of a slope blocks upward movement at its flat bottom edge, while
+						// one-way solids are passed through
+						for slope in tile.slopes_for_channel(channel) {
+							if let Some(surface) = slope.highest_surface(tile_left, tile_top, rect.x, rect.x + rect.width) {
+								let slope_bottom = tile_top + slope.bounds.y + slope.bounds.height;
+								if (surface < slope_bottom) && (rect.y >= slope_bottom) && (slope_bottom > revised_y) {
+									revised_y = slope_bottom;
+									collision = Some(revised_y);
+								}
+							}
+						}
+					}
 				}
 			}
 		}
 
 		collision
 	}
+
+	// Finds the highest slope surface under a rectangle that is below its top edge and no more than
+	// `max_drop` pixels below its bottom edge. Used to move actors up and down slopes.
+	pub fn slope_surface(&self, rect: &BoundingRect, max_drop: isize, channel: u32) -> Option<isize> {
+		let search = BoundingRect {
+			x: rect.x,
+			y: rect.y,
+			width: rect.width,
+			height: rect.height + max_drop
+		};
+
+		let mut highest: Option<isize> = None;
+		for (tile_x, tile_y) in self.tiles_in_rect(&search) {
+			if let Some(tile_ref) = self.get_tile(tile_x, tile_y) {
+				let tile = &tile_ref.tile_set.tiles[tile_ref.tile_index];
+				let tile_left = (tile_x * self.tile_width) as isize;
+				let tile_top = (tile_y * self.tile_height) as isize;
+				for slope in tile.slopes_for_channel(channel) {
+					if let Some(surface) = slope.highest_surface(tile_left, tile_top, rect.x, rect.x + rect.width) {
+						if (surface > rect.y) && (surface <= (rect.y + rect.height + max_drop)) {
+							highest = Some(highest.map_or(surface, |highest| min(highest, surface)));
+						}
+					}
+				}
+			}
+		}
+		highest
+	}
 }
 
 impl Map {
@@ -638,6 +996,33 @@ impl Map {
 		None
 	}
 
+	// Replaces tiles in a layer, returning the ones that were inside the layer. Layers are shared with the
+	// map asset, so the first change copies the layer, and later changes modify the copy in place.
+	pub fn set_tiles(&mut self, layer: usize, tiles: Vec<(usize, usize, Option<TileRef>)>) -> Vec<(usize, usize, Option<TileRef>)> {
+		let map_layer = match self.layers.get_mut(layer) {
+			Some(map_layer) => map_layer,
+			None => return Vec::new()
+		};
+		let tiles: Vec<(usize, usize, Option<TileRef>)> = tiles.into_iter()
+			.filter(|(x, y, _)| (*x < map_layer.width) && (*y < map_layer.height)).collect();
+		if tiles.is_empty() {
+			return tiles;
+		}
+		let map_layer = Rc::make_mut(map_layer);
+		for (x, y, tile) in &tiles {
+			map_layer.tiles[(y * map_layer.width) + x] = tile.clone();
+		}
+		map_layer.touch();
+		tiles
+	}
+
+	pub fn tile_properties_at(&self, x: isize, y: isize, layer: usize) -> Option<&HashMap<String, serde_json::Value>> {
+		match self.layers.get(layer) {
+			Some(layer) => layer.tile_properties_at(x, y),
+			None => None
+		}
+	}
+
 	pub fn check_collision(&self, rect: &BoundingRect, channel: u32) -> bool {
 		for layer in &self.layers {
 			if layer.check_collision(rect, channel) {
@@ -676,4 +1061,176 @@ impl Map {
 		}
 		collision
 	}
+
+	// Follows the line from (`x0`, `y0`) to (`x1`, `y1`) one pixel at a time, returning the first point that
+	// is inside tile collision. One-way platforms do not block rays. Only tiles can be hit, so the line is
+	// first clipped to the area covered by the map's layers.
+	pub fn raycast(&self, x0: isize, y0: isize, x1: isize, y1: isize, channel: u32) -> Option<RaycastHit> {
+		let (x0, y0, x1, y1) = self.clip_line(x0, y0, x1, y1)?;
+		let dx = (x1 - x0).abs();
+		let dy = -(y1 - y0).abs();
+		let step_x = if x0 < x1 { 1 } else { -1 };
+		let step_y = if y0 < y1 { 1 } else { -1 };
+		let mut error = dx + dy;
+		let mut x = x0;
+		let mut y = y0;
+
+		loop {
+			for (i, layer) in self.layers.iter().enumerate() {
+				if layer.check_point_collision(x, y, channel) {
+					let tile_x = x as usize / layer.tile_width;
+					let tile_y = y as usize / layer.tile_height;
+					if let Some(tile) = layer.get_tile(tile_x, tile_y) {
+						return Some(RaycastHit {
+							x, y,
+							layer: i,
+							tile_x, tile_y,
+							tile: tile.clone()
+						});
+					}
+				}
+			}
+
+			if (x == x1) && (y == y1) {
+				return None;
+			}
+			let double_error = error * 2;
+			if double_error >= dy {
+				error += dy;
+				x += step_x;
+			}
+			if double_error <= dx {
+				error += dx;
+				y += step_y;
+			}
+		}
+	}
+
+	// Clips a line to the pixels covered by the map's layers, returning None if it misses them entirely
+	fn clip_line(&self, x0: isize, y0: isize, x1: isize, y1: isize) -> Option<(isize, isize, isize, isize)> {
+		let right = self.layers.iter().map(|layer| layer.width * layer.tile_width).max().unwrap_or(0) as isize - 1;
+		let bottom = self.layers.iter().map(|layer| layer.height * layer.tile_height).max().unwrap_or(0) as isize - 1;
+		if (right < 0) || (bottom < 0) {
+			return None;
+		}
+
+		// Liang-Barsky clipping against each edge, in floating point so that far away endpoints can't overflow
+		let (start_x, start_y) = (x0 as f64, y0 as f64);
+		let (delta_x, delta_y) = (x1 as f64 - start_x, y1 as f64 - start_y);
+		let mut enter = 0.0;
+		let mut exit = 1.0;
+		let edges = [
+			(-delta_x, start_x),
+			(delta_x, right as f64 - start_x),
+			(-delta_y, start_y),
+			(delta_y, bottom as f64 - start_y)
+		];
+		for &(direction, distance) in &edges {
+			if direction == 0.0 {
+				if distance < 0.0 {
+					// Parallel to and outside of this edge
+					return None;
+				}
+			} else if direction < 0.0 {
+				enter = f64::max(enter, distance / direction);
+			} else {
+				exit = f64::min(exit, distance / direction);
+			}
+		}
+		if enter > exit {
+			return None;
+		}
+
+		let point = |t: f64| -> (isize, isize) {
+			let x = (start_x + (delta_x * t)).round() as isize;
+			let y = (start_y + (delta_y * t)).round() as isize;
+			(min(max(x, 0), right), min(max(y, 0), bottom))
+		};
+		let (clipped_x0, clipped_y0) = if enter > 0.0 { point(enter) } else { (x0, y0) };
+		let (clipped_x1, clipped_y1) = if exit < 1.0 { point(exit) } else { (x1, y1) };
+		Some((clipped_x0, clipped_y0, clipped_x1, clipped_y1))
+	}
+
+	pub fn line_of_sight(&self, x0: isize, y0: isize, x1: isize, y1: isize, channel: u32) -> bool {
+		self.raycast(x0, y0, x1, y1, channel).is_none()
+	}
+
+	pub fn slope_surface(&self, rect: &BoundingRect, max_drop: isize, channel: u32) -> Option<isize> {
+		let mut highest: Option<isize> = None;
+		for layer in &self.layers {
+			if let Some(surface) = layer.slope_surface(rect, max_drop, channel) {
+				highest = Some(highest.map_or(surface, |highest| min(highest, surface)));
+			}
+		}
+		highest
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tile::Slope;
+
+	// 8x4 map of 16x16 tiles with a solid tile in column 5 of the top row
+	fn test_map() -> Map {
+		let tile_set = TileSet::solid_test_tile_set(16, 16);
+		let mut layer = MapLayer::new("main", 8, 4, 16, 16, 8);
+		layer.set_tile(5, 0, Some(TileRef::new(&Rc::new(tile_set), 0)));
+		let mut map = Map::new("test");
+		map.layers.push(Rc::new(layer));
+		map
+	}
+
+	#[test]
+	fn raycast_to_far_endpoint_hits_tile() {
+		let map = test_map();
+		let hit = map.raycast(8, 8, isize::MAX / 2, 8, 0).unwrap();
+		assert_eq!((hit.x, hit.y, hit.tile_x, hit.tile_y), (80, 8, 5, 0));
+	}
+
+	#[test]
+	fn raycast_from_outside_map_enters_it() {
+		let map = test_map();
+		let hit = map.raycast(-isize::MAX / 2, 8, 127, 8, 0).unwrap();
+		assert_eq!((hit.x, hit.y), (80, 8));
+	}
+
+	#[test]
+	fn raycast_outside_map_misses() {
+		let map = test_map();
+		assert!(map.raycast(-isize::MAX / 2, -8, isize::MAX / 2, -8, 0).is_none());
+		assert!(map.raycast(8, 8, 8, isize::MAX / 2, 0).is_none());
+	}
+
+	// 4x4 map of 16x16 tiles with a single tile at (1, 1) that has no collision apart from a slope or
+	// one-way solid
+	fn single_tile_map(slopes: Vec<Slope>, one_way: Vec<BoundingRect>) -> Map {
+		let mut tile_set = TileSet::solid_test_tile_set(16, 16);
+		tile_set.tiles[0].collision.clear();
+		tile_set.tiles[0].slopes = slopes;
+		tile_set.tiles[0].one_way = one_way;
+		let mut layer = MapLayer::new("main", 4, 4, 16, 16, 8);
+		layer.set_tile(1, 1, Some(TileRef::new(&Rc::new(tile_set), 0)));
+		let mut map = Map::new("test");
+		map.layers.push(Rc::new(layer));
+		map
+	}
+
+	#[test]
+	fn slopes_block_from_below_and_one_way_solids_do_not() {
+		let rect = BoundingRect { x: 24, y: 40, width: 8, height: 8 };
+
+		// Jumping into the solid right half of a slope stops at the bottom of the tile
+		let slope = Slope {
+			bounds: BoundingRect { x: 0, y: 0, width: 16, height: 16 },
+			left_height: 0,
+			right_height: 16
+		};
+		let map = single_tile_map(vec![slope], Vec::new());
+		assert_eq!(map.sweep_collision_y(&rect, 20, 0), Some(32));
+		assert!(!map.check_collision(&BoundingRect { x: 24, y: 32, width: 8, height: 8 }, 0));
+
+		let map = single_tile_map(Vec::new(), vec![BoundingRect { x: 0, y: 0, width: 16, height: 4 }]);
+		assert_eq!(map.sweep_collision_y(&rect, 10, 0), None);
+	}
 }
@@ -33,4 +33,8 @@ impl Palette {
 	pub fn convert_color(color: u16) -> u32 {
 		((color as u32 & 0x1f) << 3) | ((color as u32 & 0x3e0) << 6) | ((color as u32 & 0x7c00) << 9)
 	}
+
+	pub fn convert_to_raw_color(color: u32) -> u16 {
+		(((color >> 3) & 0x1f) | ((color >> 6) & 0x3e0) | ((color >> 9) & 0x7c00)) as u16
+	}
 }
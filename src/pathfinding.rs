@@ -0,0 +1,414 @@
+use std::cmp::{min, max, Ordering};
+use std::collections::{BinaryHeap, HashMap};
+use actor::BoundingRect;
+use map::Map;
+
+// Cost of moving one cell straight and diagonally
+const STRAIGHT_COST: usize = 10;
+const DIAGONAL_COST: usize = 14;
+// Extra cost of a jump, so that walking is preferred when it is just as short
+const JUMP_COST: usize = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathfindingMode {
+	// Movement in 8 directions. Diagonal moves are not allowed to cut corners.
+	TopDown,
+	// Walking along the ground, falling off of edges, and jumping up to `jump_height` cells up and
+	// `jump_distance` cells across
+	Platformer { jump_height: usize, jump_distance: usize }
+}
+
+// How an actor gets to a waypoint from the one before it
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathMove {
+	Walk,
+	// Walking off of an edge and falling to the ground below
+	Fall,
+	Jump
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waypoint {
+	pub x: isize,
+	pub y: isize,
+	pub movement: PathMove
+}
+
+// Grid of the cells in a map that an actor of a given size can occupy without colliding, using the
+// tile size of the map's first normal layer. An actor occupies a cell when its collision bounds have
+// their left edge at the left of the cell and their bottom edge at the bottom of the cell.
+pub struct NavigationGrid {
+	pub width: usize,
+	pub height: usize,
+	pub cell_width: usize,
+	pub cell_height: usize,
+	pub actor_width: isize,
+	pub actor_height: isize,
+	open: Vec<bool>
+}
+
+#[derive(PartialEq, Eq)]
+struct SearchNode {
+	estimate: usize,
+	cost: usize,
+	cell: (usize, usize)
+}
+
+impl Ord for SearchNode {
+	fn cmp(&self, other: &SearchNode) -> Ordering {
+		// Reversed so that the binary heap gives the lowest estimate first
+		other.estimate.cmp(&self.estimate).then_with(|| self.cost.cmp(&other.cost))
+	}
+}
+
+impl PartialOrd for SearchNode {
+	fn partial_cmp(&self, other: &SearchNode) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl NavigationGrid {
+	pub fn from_map(map: &Map, channel: u32, actor_width: isize, actor_height: isize) -> NavigationGrid {
+		let (width, height, cell_width, cell_height) = match map.layers.iter().find(|layer| !layer.effect) {
+			Some(layer) => (layer.width, layer.height, layer.tile_width, layer.tile_height),
+			None => (0, 0, 1, 1)
+		};
+
+		let mut grid = NavigationGrid {
+			width, height, cell_width, cell_height, actor_width, actor_height,
+			open: Vec::with_capacity(width * height)
+		};
+		for cell_y in 0..height {
+			for cell_x in 0..width {
+				let (x, y) = grid.cell_position(cell_x, cell_y);
+				let open = !map.check_collision(&BoundingRect {
+					x, y,
+					width: actor_width,
+					height: actor_height
+				}, channel);
+				grid.open.push(open);
+			}
+		}
+		grid
+	}
+
+	// Position of the actor's collision bounds when occupying a cell, in map coordinates
+	pub fn cell_position(&self, cell_x: usize, cell_y: usize) -> (isize, isize) {
+		((cell_x * self.cell_width) as isize, ((cell_y + 1) * self.cell_height) as isize - self.actor_height)
+	}
+
+	// Cell occupied by an actor with its collision bounds at the given position
+	pub fn cell_at(&self, x: isize, y: isize) -> Option<(usize, usize)> {
+		let cell_x = x.div_euclid(self.cell_width as isize);
+		let cell_y = (y + self.actor_height - 1).div_euclid(self.cell_height as isize);
+		if (cell_x < 0) || (cell_y < 0) || (cell_x >= self.width as isize) || (cell_y >= self.height as isize) {
+			return None;
+		}
+		Some((cell_x as usize, cell_y as usize))
+	}
+
+	pub fn is_open(&self, cell_x: isize, cell_y: isize) -> bool {
+		if (cell_x < 0) || (cell_y < 0) || (cell_x >= self.width as isize) || (cell_y >= self.height as isize) {
+			return false;
+		}
+		self.open[(cell_y as usize * self.width) + cell_x as usize]
+	}
+
+	// Cells can be stood on when they are open and the cell below is not
+	pub fn is_standable(&self, cell_x: isize, cell_y: isize) -> bool {
+		self.is_open(cell_x, cell_y) && !self.is_open(cell_x, cell_y + 1)
+	}
+
+	// First cell that can be stood on when falling from a cell
+	fn landing_cell(&self, cell_x: isize, cell_y: isize) -> Option<isize> {
+		let mut y = cell_y;
+		while self.is_open(cell_x, y) {
+			if !self.is_open(cell_x, y + 1) {
+				return Some(y);
+			}
+			y += 1;
+		}
+		None
+	}
+
+	fn is_column_open(&self, cell_x: isize, from_y: isize, to_y: isize) -> bool {
+		(min(from_y, to_y) ..= max(from_y, to_y)).all(|y| self.is_open(cell_x, y))
+	}
+
+	fn is_row_open(&self, cell_y: isize, from_x: isize, to_x: isize) -> bool {
+		(min(from_x, to_x) ..= max(from_x, to_x)).all(|x| self.is_open(x, cell_y))
+	}
+
+	// Cells reachable in one move, with the cost of each move
+	fn neighbors(&self, cell_x: isize, cell_y: isize, mode: &PathfindingMode) -> Vec<((isize, isize), usize)> {
+		let mut result = Vec::new();
+		match mode {
+			PathfindingMode::TopDown => {
+				for dy in -1 ..= 1 {
+					for dx in -1 ..= 1 {
+						if ((dx == 0) && (dy == 0)) || !self.is_open(cell_x + dx, cell_y + dy) {
+							continue;
+						}
+						if (dx != 0) && (dy != 0) {
+							if !self.is_open(cell_x + dx, cell_y) || !self.is_open(cell_x, cell_y + dy) {
+								continue;
+							}
+							result.push(((cell_x + dx, cell_y + dy), DIAGONAL_COST));
+						} else {
+							result.push(((cell_x + dx, cell_y + dy), STRAIGHT_COST));
+						}
+					}
+				}
+			},
+			PathfindingMode::Platformer { jump_height, jump_distance } => {
+				// Walk to either side, falling if there is no ground there
+				for dx in &[-1, 1] {
+					if let Some(landing_y) = self.landing_cell(cell_x + dx, cell_y) {
+						let fall = (landing_y - cell_y) as usize;
+						result.push(((cell_x + dx, landing_y), STRAIGHT_COST * (1 + fall)));
+					}
+				}
+
+				// Jump by rising to the level of the higher of the two cells, moving across, then falling
+				let jump_height = *jump_height as isize;
+				let jump_distance = *jump_distance as isize;
+				for target_y in (cell_y - jump_height) ..= (cell_y + jump_height) {
+					for target_x in (cell_x - jump_distance) ..= (cell_x + jump_distance) {
+						let dx = target_x - cell_x;
+						if (dx == 0) || ((dx.abs() == 1) && (target_y >= cell_y)) {
+							// Walking already covers these
+							continue;
+						}
+						if !self.is_standable(target_x, target_y) {
+							continue;
+						}
+						let apex = min(cell_y, target_y);
+						if self.is_column_open(cell_x, cell_y, apex) && self.is_row_open(apex, cell_x, target_x) &&
+							self.is_column_open(target_x, apex, target_y) {
+							let distance = (dx.abs() + (cell_y - apex) + (target_y - apex)) as usize;
+							result.push(((target_x, target_y), (STRAIGHT_COST * distance) + JUMP_COST));
+						}
+					}
+				}
+			}
+		}
+		result
+	}
+
+	fn heuristic(from: (usize, usize), to: (usize, usize), mode: &PathfindingMode) -> usize {
+		let dx = (from.0 as isize - to.0 as isize).unsigned_abs();
+		let dy = (from.1 as isize - to.1 as isize).unsigned_abs();
+		match mode {
+			PathfindingMode::TopDown => (STRAIGHT_COST * max(dx, dy)) + ((DIAGONAL_COST - STRAIGHT_COST) * min(dx, dy)),
+			PathfindingMode::Platformer { .. } => STRAIGHT_COST * (dx + dy)
+		}
+	}
+
+	// Finds the shortest path between two cells using A*, returning every cell visited along the way
+	// including the start and goal. In platformer mode, cells in the air are moved down to the ground
+	// below them first.
+	pub fn find_path_cells(&self, start: (usize, usize), goal: (usize, usize), mode: &PathfindingMode) -> Option<Vec<(usize, usize)>> {
+		let (start, goal) = match mode {
+			PathfindingMode::TopDown => (start, goal),
+			PathfindingMode::Platformer { .. } => {
+				let start_y = self.landing_cell(start.0 as isize, start.1 as isize)?;
+				let goal_y = self.landing_cell(goal.0 as isize, goal.1 as isize)?;
+				((start.0, start_y as usize), (goal.0, goal_y as usize))
+			}
+		};
+		if !self.is_open(start.0 as isize, start.1 as isize) || !self.is_open(goal.0 as isize, goal.1 as isize) {
+			return None;
+		}
+
+		let mut open_set = BinaryHeap::new();
+		let mut costs: HashMap<(usize, usize), usize> = HashMap::new();
+		let mut came_from: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+		costs.insert(start, 0);
+		open_set.push(SearchNode {
+			estimate: NavigationGrid::heuristic(start, goal, mode),
+			cost: 0,
+			cell: start
+		});
+
+		while let Some(node) = open_set.pop() {
+			if node.cell == goal {
+				let mut path = vec![goal];
+				let mut cell = goal;
+				while let Some(prev) = came_from.get(&cell) {
+					path.push(*prev);
+					cell = *prev;
+				}
+				path.reverse();
+				return Some(path);
+			}
+			if node.cost > costs[&node.cell] {
+				// Already reached this cell more cheaply
+				continue;
+			}
+
+			for ((x, y), move_cost) in self.neighbors(node.cell.0 as isize, node.cell.1 as isize, mode) {
+				let cell = (x as usize, y as usize);
+				let cost = node.cost + move_cost;
+				let better = match costs.get(&cell) {
+					Some(existing) => cost < *existing,
+					None => true
+				};
+				if better {
+					costs.insert(cell, cost);
+					came_from.insert(cell, node.cell);
+					open_set.push(SearchNode {
+						estimate: cost + NavigationGrid::heuristic(cell, goal, mode),
+						cost, cell
+					});
+				}
+			}
+		}
+		None
+	}
+
+	// Finds a path for an actor with its collision bounds at (`start_x`, `start_y`) to reach
+	// (`goal_x`, `goal_y`). Returns the positions of the collision bounds at the end of each move,
+	// ending at the goal cell. Each jump and fall is its own waypoint, and consecutive walking steps in
+	// the same direction are combined into one.
+	pub fn find_path(&self, start_x: isize, start_y: isize, goal_x: isize, goal_y: isize,
+		mode: &PathfindingMode) -> Option<Vec<Waypoint>> {
+		let start = self.cell_at(start_x, start_y)?;
+		let goal = self.cell_at(goal_x, goal_y)?;
+		let cells = self.find_path_cells(start, goal, mode)?;
+
+		let mut waypoints: Vec<Waypoint> = Vec::new();
+		let mut prev_step = None;
+		for pair in cells.windows(2) {
+			let (from, to) = (pair[0], pair[1]);
+			let step = (to.0 as isize - from.0 as isize, to.1 as isize - from.1 as isize);
+			let movement = NavigationGrid::move_for_step(step, mode);
+			let (x, y) = self.cell_position(to.0, to.1);
+			match waypoints.last_mut() {
+				Some(last) if (movement == PathMove::Walk) && (last.movement == PathMove::Walk) &&
+					(prev_step == Some(step)) => {
+					// Continue walking in the same direction
+					last.x = x;
+					last.y = y;
+				},
+				_ => waypoints.push(Waypoint { x, y, movement })
+			}
+			prev_step = Some(step);
+		}
+		if waypoints.is_empty() {
+			// Already at the goal
+			let (x, y) = self.cell_position(cells[0].0, cells[0].1);
+			waypoints.push(Waypoint { x, y, movement: PathMove::Walk });
+		}
+		Some(waypoints)
+	}
+
+	fn move_for_step(step: (isize, isize), mode: &PathfindingMode) -> PathMove {
+		match mode {
+			PathfindingMode::TopDown => PathMove::Walk,
+			PathfindingMode::Platformer { .. } => {
+				// Steps to an adjacent column that don't go up are walks, as jumps never cover them
+				if (step.0.abs() == 1) && (step.1 == 0) {
+					PathMove::Walk
+				} else if (step.0.abs() == 1) && (step.1 > 0) {
+					PathMove::Fall
+				} else {
+					PathMove::Jump
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Builds a grid of 16x16 cells for a 16x16 actor, with `#` marking blocked cells
+	fn grid(rows: &[&str]) -> NavigationGrid {
+		NavigationGrid {
+			width: rows[0].len(),
+			height: rows.len(),
+			cell_width: 16,
+			cell_height: 16,
+			actor_width: 16,
+			actor_height: 16,
+			open: rows.iter().flat_map(|row| row.chars().map(|cell| cell != '#')).collect()
+		}
+	}
+
+	fn waypoint(grid: &NavigationGrid, cell_x: usize, cell_y: usize, movement: PathMove) -> Waypoint {
+		let (x, y) = grid.cell_position(cell_x, cell_y);
+		Waypoint { x, y, movement }
+	}
+
+	#[test]
+	fn top_down_path_goes_around_walls() {
+		let grid = grid(&[
+			"....",
+			"###.",
+			"...."
+		]);
+		let path = grid.find_path_cells((0, 0), (0, 2), &PathfindingMode::TopDown).unwrap();
+		// Diagonal moves can't cut the corners of the wall
+		assert_eq!(path, vec![(0, 0), (1, 0), (2, 0), (3, 0), (3, 1), (3, 2), (2, 2), (1, 2), (0, 2)]);
+	}
+
+	#[test]
+	fn unreachable_goal_has_no_path() {
+		let grid = grid(&[
+			"..#.",
+			"..#.",
+			"..#."
+		]);
+		assert_eq!(grid.find_path_cells((0, 0), (3, 0), &PathfindingMode::TopDown), None);
+	}
+
+	#[test]
+	fn walking_steps_are_combined() {
+		let grid = grid(&[
+			".....",
+			"#####"
+		]);
+		let mode = PathfindingMode::Platformer { jump_height: 1, jump_distance: 3 };
+		let (start_x, start_y) = grid.cell_position(0, 0);
+		let (goal_x, goal_y) = grid.cell_position(4, 0);
+		let path = grid.find_path(start_x, start_y, goal_x, goal_y, &mode).unwrap();
+		assert_eq!(path, vec![waypoint(&grid, 4, 0, PathMove::Walk)]);
+	}
+
+	#[test]
+	fn repeated_jumps_keep_each_landing() {
+		let grid = grid(&[
+			".......",
+			".......",
+			"#..#..#",
+			"#..#..#"
+		]);
+		let mode = PathfindingMode::Platformer { jump_height: 1, jump_distance: 3 };
+		let (start_x, start_y) = grid.cell_position(0, 1);
+		let (goal_x, goal_y) = grid.cell_position(6, 1);
+		let path = grid.find_path(start_x, start_y, goal_x, goal_y, &mode).unwrap();
+		assert_eq!(path, vec![
+			waypoint(&grid, 3, 1, PathMove::Jump),
+			waypoint(&grid, 6, 1, PathMove::Jump)
+		]);
+	}
+
+	#[test]
+	fn falls_are_separate_waypoints() {
+		let grid = grid(&[
+			"....",
+			"##..",
+			"####"
+		]);
+		let mode = PathfindingMode::Platformer { jump_height: 0, jump_distance: 1 };
+		let (start_x, start_y) = grid.cell_position(0, 0);
+		let (goal_x, goal_y) = grid.cell_position(3, 1);
+		let path = grid.find_path(start_x, start_y, goal_x, goal_y, &mode).unwrap();
+		assert_eq!(path, vec![
+			waypoint(&grid, 1, 0, PathMove::Walk),
+			waypoint(&grid, 2, 1, PathMove::Fall),
+			waypoint(&grid, 3, 1, PathMove::Walk)
+		]);
+	}
+}
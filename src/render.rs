@@ -1,24 +1,31 @@
 extern crate sdl2;
 extern crate byteorder;
+extern crate rayon;
 
 use std::time;
+use std::cmp::{min, max};
 use std::rc::Rc;
+use std::time::Duration;
+use std::cell::{Ref, RefMut};
 use self::byteorder::{ByteOrder, LittleEndian};
+use self::rayon::{ThreadPool, ThreadPoolBuilder};
 use game::GameState;
-use map::{MapLayer, BlendMode};
-use tile::{TileSet, PaletteWithOffset};
-use ui::{UILayerRenderer, UILayerContents, UILayer};
-use sprite::SpriteAnimation;
+use std::collections::HashMap;
+use map::{Map, MapLayer, BlendMode, TileRef, LayerAnimationState};
+use tile::{TileSet, Tile, Animation, PaletteWithOffset, TileAnimationMode};
+use ui::{UILayerRenderer, UILayerContents, UILayer, UILayerRef, UILayoutRef};
+use viewport::Viewport;
+use sprite::{SpriteAnimation, SpriteFrame};
 use palette::Palette;
-use actor::BoundingRect;
+use actor::{Actor, ActorRef, ActorInfo, BoundingRect, SpriteWithOffset, SpriteLayerPlacement, DepthSortMode};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ResolutionTargetMode {
 	FixedVerticalResolution,
 	PixelPerfect
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ResolutionTarget {
 	mode: ResolutionTargetMode,
 	min_height: usize,
@@ -33,6 +40,132 @@ pub struct RenderSize {
 	pub height: usize
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowMode {
+	Windowed,
+	Borderless,
+	Fullscreen
+}
+
+// Display options that can be changed at runtime. A window scale sizes the window to an integer
+// multiple of the render size when windowed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplaySettings {
+	pub resolution_target: ResolutionTarget,
+	pub window_mode: WindowMode,
+	pub window_scale: Option<usize>,
+	pub vsync: bool
+}
+
+// Rendered pixels in a single contiguous buffer. Rows start every `stride` pixels, which may be larger
+// than the width when the buffer is padded for the upload target.
+#[derive(Clone)]
+pub struct FrameBuffer {
+	pub width: usize,
+	pub height: usize,
+	pub stride: usize,
+	pub pixels: Vec<u32>
+}
+
+// Rows of a frame buffer to render into, starting at row `y` of the frame. Bands of the same frame
+// can be rendered by separate threads.
+struct FrameBand<'a> {
+	y: usize,
+	width: usize,
+	height: usize,
+	stride: usize,
+	pixels: &'a mut [u32]
+}
+
+// Single rendering operation, recorded up front so that the frame can be rendered in bands by multiple
+// threads. Commands only hold plain data such as pixel and palette slices, resolved from the reference
+// counted assets on the main thread, so that rendering threads never touch an `Rc` or `RefCell`.
+enum DrawCommand<'a> {
+	Fill(BoundingRect, u32),
+	Layer {
+		bounds: BoundingRect,
+		source: LayerSource<'a>,
+		scroll_x: usize,
+		scroll_y: usize,
+		tile_depth: usize,
+		blending: Blending
+	},
+	Sprite {
+		clip: BoundingRect,
+		x: isize,
+		y: isize,
+		image: SpriteImage<'a>,
+		blending: Blending
+	},
+	Fade(u8)
+}
+
+// How drawn pixels are combined with the frame, an `alpha` of zero is fully opaque
+#[derive(Clone)]
+struct Blending {
+	mode: BlendMode,
+	alpha: u8
+}
+
+// Area of the frame a map layer is drawn into, with the scroll position and game frame it is drawn at
+struct LayerView {
+	bounds: BoundingRect,
+	frame: usize,
+	scroll_x: isize,
+	scroll_y: isize
+}
+
+// Actor sprite along with the slot in the map layer stack it renders in. Sprites in slot `n` render
+// just before map layer `n`.
+struct PlacedSprite<'a> {
+	slot: usize,
+	actor_info: &'a ActorInfo,
+	sprite: &'a SpriteWithOffset
+}
+
+// Pixels of a layer to draw, either from its pre-rendered cache or tile by tile
+enum LayerSource<'a> {
+	Tiles(LayerTiles<'a>),
+	Cache(LayerCacheView<'a>)
+}
+
+// Tile data for the current animation frame along with the palette entries to draw it with
+#[derive(Clone, Copy)]
+struct TileImage<'a> {
+	data: &'a [u8],
+	palette: Option<&'a [u32]>
+}
+
+// Tiles of a layer covering an area, starting at (`left_tile`, `top_tile`) before wrapping around
+// the layer
+struct LayerTiles<'a> {
+	tile_width: usize,
+	tile_height: usize,
+	tile_depth: usize,
+	left_tile: usize,
+	top_tile: usize,
+	columns: usize,
+	tiles: Vec<Option<TileImage<'a>>>
+}
+
+// Pre-rendered pixels of a layer, starting at (`x`, `y`) in layer coordinates
+struct LayerCacheView<'a> {
+	pixels: &'a FrameBuffer,
+	x: usize,
+	y: usize
+}
+
+// Sprite animation frame to draw, with the origin of the frame within its pixels
+struct SpriteImage<'a> {
+	data: &'a [u8],
+	width: usize,
+	height: usize,
+	depth: usize,
+	origin_x: isize,
+	origin_y: isize,
+	palette: Option<&'a [u32]>
+}
+
 pub struct FrameRateTextRenderer {
 	start_time: time::Instant,
 	last_elapsed_secs: u64,
@@ -40,6 +173,53 @@ pub struct FrameRateTextRenderer {
 	frame_rate: usize
 }
 
+pub struct FrameTimeTextRenderer {
+	start_time: time::Instant,
+	last_elapsed_secs: u64,
+	frames: u32,
+	total_render_time: Duration,
+	average_render_time: Duration
+}
+
+// Pixel value used for transparent areas of a layer cache, never produced by palette conversion
+const LAYER_CACHE_TRANSPARENT: u32 = 0xff000000;
+
+// Number of tiles rendered past each edge of the visible area, so that small scrolls reuse the cache
+const LAYER_CACHE_MARGIN_TILES: usize = 4;
+
+// Source of tile animation timing for a layer. Layers without animation state, such as UI layers, are
+// animated from the game frame.
+enum TileAnimationClock<'a> {
+	Frame(usize),
+	Layer(&'a LayerAnimationState)
+}
+
+#[derive(Clone)]
+pub struct LayerCache {
+	// Address and generation of the cached layer
+	layer: Option<(usize, usize)>,
+	animated_tiles: Vec<TileRef>,
+	animation_frames: Vec<usize>,
+	trigger_frames: HashMap<(usize, usize), usize>,
+	valid: bool,
+	x: usize,
+	y: usize,
+	width: usize,
+	height: usize,
+	pixels: FrameBuffer
+}
+
+impl DisplaySettings {
+	pub fn new(resolution_target: ResolutionTarget) -> DisplaySettings {
+		DisplaySettings {
+			resolution_target,
+			window_mode: WindowMode::Windowed,
+			window_scale: None,
+			vsync: true
+		}
+	}
+}
+
 impl ResolutionTarget {
 	pub fn fixed_vertical_resolution(height: usize) -> ResolutionTarget {
 		ResolutionTarget {
@@ -123,6 +303,182 @@ impl ResolutionTarget {
 	}
 }
 
+impl FrameBuffer {
+	pub fn new(width: usize, height: usize) -> FrameBuffer {
+		FrameBuffer::with_stride(width, height, width)
+	}
+
+	pub fn with_stride(width: usize, height: usize, stride: usize) -> FrameBuffer {
+		assert!(stride >= width, "Frame buffer stride is smaller than its width");
+		FrameBuffer {
+			width, height, stride,
+			pixels: vec![0; stride * height]
+		}
+	}
+
+	pub fn size(&self) -> RenderSize {
+		RenderSize { width: self.width, height: self.height }
+	}
+
+	pub fn resize(&mut self, width: usize, height: usize) {
+		self.width = width;
+		self.height = height;
+		self.stride = width;
+		self.pixels.resize(width * height, 0);
+	}
+
+	pub fn row(&self, y: usize) -> &[u32] {
+		&self.pixels[y * self.stride .. (y * self.stride) + self.width]
+	}
+
+	pub fn row_mut(&mut self, y: usize) -> &mut [u32] {
+		&mut self.pixels[y * self.stride .. (y * self.stride) + self.width]
+	}
+
+	pub fn row_span(&self, y: usize, x: usize, width: usize) -> &[u32] {
+		&self.row(y)[x .. x + width]
+	}
+
+	pub fn row_span_mut(&mut self, y: usize, x: usize, width: usize) -> &mut [u32] {
+		&mut self.row_mut(y)[x .. x + width]
+	}
+
+	// Entire frame as a single band
+	fn band_mut(&mut self) -> FrameBand<'_> {
+		FrameBand {
+			y: 0,
+			width: self.width,
+			height: self.height,
+			stride: self.stride,
+			pixels: &mut self.pixels
+		}
+	}
+
+	// Splits the frame into bands of `rows` rows each, with a shorter final band if the height is not
+	// a multiple of `rows`
+	fn bands_mut(&mut self, rows: usize) -> Vec<FrameBand<'_>> {
+		let width = self.width;
+		let stride = self.stride;
+		self.pixels[.. stride * self.height].chunks_mut(rows * stride).enumerate().map(|(i, pixels)| FrameBand {
+			y: i * rows,
+			width,
+			height: pixels.len() / stride,
+			stride,
+			pixels
+		}).collect()
+	}
+
+	pub fn fill(&mut self, color: u32) {
+		for pixel in &mut self.pixels {
+			*pixel = color;
+		}
+	}
+
+	pub fn copy_to_bytes(&self, buffer: &mut [u8], pitch: usize) {
+		if (pitch == self.stride * 4) && (self.stride == self.width) {
+			// Layouts match, upload entire frame at once
+			LittleEndian::write_u32_into(&self.pixels, &mut buffer[.. self.pixels.len() * 4]);
+		} else {
+			for y in 0..self.height {
+				LittleEndian::write_u32_into(self.row(y), &mut buffer[y * pitch .. (y * pitch) + (self.width * 4)]);
+			}
+		}
+	}
+
+	// Appends 16-bit color data for a span of pixels, pixels outside of the buffer or matching
+	// `transparent_color` are written as transparent
+	fn encode_raw_colors(&self, data: &mut Vec<u8>, x: usize, y: usize, width: usize, transparent_color: Option<u32>) {
+		for i in x .. x + width {
+			let color = if (y < self.height) && (i < self.width) {
+				let pixel = self.row(y)[i];
+				if Some(pixel) == transparent_color {
+					0x8000
+				} else {
+					Palette::convert_to_raw_color(pixel)
+				}
+			} else {
+				0x8000
+			};
+			let mut raw = [0; 2];
+			LittleEndian::write_u16(&mut raw, color);
+			data.extend_from_slice(&raw);
+		}
+	}
+
+	// Creates a single frame, 16-bit sprite animation from the contents of the buffer
+	pub fn to_sprite_animation(&self, name: &str, transparent_color: Option<u32>) -> SpriteAnimation {
+		let mut data = Vec::with_capacity(self.width * self.height * 2);
+		for y in 0..self.height {
+			self.encode_raw_colors(&mut data, 0, y, self.width, transparent_color);
+		}
+
+		SpriteAnimation {
+			name: name.to_string(),
+			width: self.width,
+			height: self.height,
+			depth: 16,
+			palette: None,
+			data,
+			animation: Animation::new(vec![1]),
+			frames: 1,
+			looping: true,
+			events: Vec::new(),
+			boxes: Vec::new(),
+			frame_layouts: SpriteFrame::uniform(self.width, self.height, 16, 1)
+		}
+	}
+
+	// Creates a 16-bit map layer from the contents of the buffer, split into tiles of the given size
+	pub fn to_map_layer(&self, name: &str, tile_width: usize, tile_height: usize,
+		transparent_color: Option<u32>) -> MapLayer {
+		let width = self.width.div_ceil(tile_width);
+		let height = self.height.div_ceil(tile_height);
+
+		let mut tile_set = TileSet::new(name, tile_width, tile_height, 16, None);
+		for tile_y in 0..height {
+			for tile_x in 0..width {
+				let mut data = Vec::with_capacity(tile_set.single_frame_size);
+				for y in 0..tile_height {
+					self.encode_raw_colors(&mut data, tile_x * tile_width, (tile_y * tile_height) + y,
+						tile_width, transparent_color);
+				}
+				tile_set.push(Tile {
+					palette: None,
+					data,
+					collision: Vec::new(),
+					collision_channels: HashMap::new(),
+					slopes: Vec::new(),
+					one_way: Vec::new(),
+					slope_channels: HashMap::new(),
+					one_way_channels: HashMap::new(),
+					properties: HashMap::new(),
+					animation: None,
+					animation_mode: TileAnimationMode::Loop
+				});
+			}
+		}
+
+		let tile_set = Rc::new(tile_set);
+		let mut layer = MapLayer::new(name, width, height, tile_width, tile_height, 16);
+		for y in 0..height {
+			for x in 0..width {
+				layer.set_tile(x, y, Some(TileRef::new(&tile_set, (y * width) + x)));
+			}
+		}
+		layer
+	}
+}
+
+impl<'a> FrameBand<'a> {
+	fn row_mut(&mut self, y: usize) -> &mut [u32] {
+		&mut self.pixels[y * self.stride .. (y * self.stride) + self.width]
+	}
+
+	fn row_span_mut(&mut self, y: usize, x: usize, width: usize) -> &mut [u32] {
+		&mut self.row_mut(y)[x .. x + width]
+	}
+}
+
 impl FrameRateTextRenderer {
 	pub fn new() -> FrameRateTextRenderer {
 		FrameRateTextRenderer {
@@ -158,6 +514,289 @@ impl UILayerRenderer for FrameRateTextRenderer {
 	}
 }
 
+impl Default for FrameTimeTextRenderer {
+	fn default() -> FrameTimeTextRenderer {
+		FrameTimeTextRenderer::new()
+	}
+}
+
+impl FrameTimeTextRenderer {
+	pub fn new() -> FrameTimeTextRenderer {
+		FrameTimeTextRenderer {
+			start_time: time::Instant::now(),
+			last_elapsed_secs: 0,
+			frames: 0,
+			total_render_time: Duration::new(0, 0),
+			average_render_time: Duration::new(0, 0)
+		}
+	}
+
+	pub fn new_ui_layer(font_tile_set: Rc<TileSet>, font_base: u8) -> UILayer {
+		let mut layer = UILayer::new(font_tile_set.width, font_tile_set.height, font_tile_set.depth);
+		layer.set_font(font_tile_set, font_base);
+		layer.renderer = Some(Box::new(FrameTimeTextRenderer::new()));
+		layer
+	}
+}
+
+impl UILayerRenderer for FrameTimeTextRenderer {
+	fn update(&mut self, layer: &mut UILayerContents, game_state: &GameState) {
+		self.total_render_time += game_state.render_time;
+		self.frames += 1;
+
+		let elapsed_secs = self.start_time.elapsed().as_secs();
+		if elapsed_secs != self.last_elapsed_secs {
+			self.last_elapsed_secs = elapsed_secs;
+			self.average_render_time = self.total_render_time / self.frames;
+			self.total_render_time = Duration::new(0, 0);
+			self.frames = 0;
+		}
+
+		layer.clear();
+		let text = format!("{:.2} ms", self.average_render_time.as_secs_f64() * 1000.0);
+		let x = (layer.width() - text.len() as isize) - 1;
+		layer.write(x, 1, &text);
+	}
+}
+
+impl<'a> TileAnimationClock<'a> {
+	fn frame_for_tile(&self, tile_ref: &TileRef, x: usize, y: usize) -> usize {
+		match self {
+			TileAnimationClock::Frame(frame) => tile_ref.frame_for_time(*frame, None),
+			TileAnimationClock::Layer(animation) => animation.frame_for_tile(tile_ref, x, y)
+		}
+	}
+
+	// Current frames of the triggered tiles that are playing, by position
+	fn trigger_frames<'b>(&'b self, layer: &'b MapLayer) -> impl Iterator<Item = ((usize, usize), usize)> + 'b {
+		let animation = match self {
+			TileAnimationClock::Layer(animation) => Some(*animation),
+			TileAnimationClock::Frame(_) => None
+		};
+		animation.into_iter().flat_map(move |animation| animation.triggers.keys().filter_map(move |&(x, y)| {
+			if (x < layer.width) && (y < layer.height) {
+				layer.get_tile(x, y).as_ref().map(|tile_ref| ((x, y), animation.frame_for_tile(tile_ref, x, y)))
+			} else {
+				None
+			}
+		}))
+	}
+}
+
+impl Default for LayerCache {
+	fn default() -> LayerCache {
+		LayerCache::new()
+	}
+}
+
+impl LayerCache {
+	pub fn new() -> LayerCache {
+		LayerCache {
+			layer: None,
+			animated_tiles: Vec::new(),
+			animation_frames: Vec::new(),
+			trigger_frames: HashMap::new(),
+			valid: false,
+			x: 0,
+			y: 0,
+			width: 0,
+			height: 0,
+			pixels: FrameBuffer::new(0, 0)
+		}
+	}
+
+	pub fn invalidate(&mut self) {
+		self.valid = false;
+	}
+
+	fn validate(&mut self, layer: &Rc<MapLayer>, clock: &TileAnimationClock) {
+		// Layers get a new generation when they are created and whenever their tiles change, and copies
+		// keep the generation of the tiles they copied. The address and generation together identify the
+		// contents without holding a reference that would prevent modifying the layer in place.
+		let key = (Rc::as_ptr(layer) as usize, layer.generation);
+		if self.layer != Some(key) {
+			self.layer = Some(key);
+			self.animated_tiles.clear();
+			self.animation_frames.clear();
+			self.trigger_frames.clear();
+			for tile_ref in layer.tiles.iter().flatten() {
+				// Triggered tiles animate per position and are tracked separately
+				if tile_ref.tile_set.tile_animation(tile_ref.tile_index).is_some() &&
+					(*tile_ref.animation_mode() != TileAnimationMode::Trigger) &&
+					!self.animated_tiles.iter().any(|animated| Rc::ptr_eq(&animated.tile_set, &tile_ref.tile_set) &&
+						(animated.tile_index == tile_ref.tile_index)) {
+					self.animated_tiles.push(tile_ref.clone());
+				}
+			}
+			self.valid = false;
+			return;
+		}
+
+		// Any tile animation advancing to a new frame requires a new render
+		for (tile_ref, cached_frame) in self.animated_tiles.iter().zip(&self.animation_frames) {
+			if clock.frame_for_tile(tile_ref, 0, 0) != *cached_frame {
+				self.valid = false;
+				return;
+			}
+		}
+		let mut playing = 0;
+		let triggers_match = clock.trigger_frames(layer).all(|(position, frame)| {
+			playing += 1;
+			self.trigger_frames.get(&position) == Some(&frame)
+		});
+		if !triggers_match || (playing != self.trigger_frames.len()) {
+			self.valid = false;
+		}
+	}
+
+	fn update(&mut self, clock: &TileAnimationClock, layer: &MapLayer, scroll_x: usize, scroll_y: usize, width: usize, height: usize) {
+		// Render into the cached strip only when the visible area leaves it
+		if self.contains(scroll_x, scroll_y, width, height) {
+			return;
+		}
+		self.cover(layer, scroll_x, scroll_y, width, height);
+		match layer.tile_depth {
+			4 => self.render(clock, layer, &render_tile_4bit),
+			8 => self.render(clock, layer, &render_tile_8bit),
+			16 => self.render(clock, layer, &render_tile_16bit),
+			_ => panic!("Invalid tile bit depth {}", layer.tile_depth)
+		};
+	}
+
+	fn contains(&self, scroll_x: usize, scroll_y: usize, width: usize, height: usize) -> bool {
+		self.valid && (scroll_x >= self.x) && (scroll_y >= self.y) &&
+			((scroll_x + width) <= (self.x + self.width)) && ((scroll_y + height) <= (self.y + self.height))
+	}
+
+	// Moves the cached area to cover the visible area, aligned to tiles and extended by a margin on each side
+	fn cover(&mut self, layer: &MapLayer, scroll_x: usize, scroll_y: usize, width: usize, height: usize) {
+		self.x = (scroll_x / layer.tile_width).saturating_sub(LAYER_CACHE_MARGIN_TILES) * layer.tile_width;
+		self.y = (scroll_y / layer.tile_height).saturating_sub(LAYER_CACHE_MARGIN_TILES) * layer.tile_height;
+		self.width = (((scroll_x + width).div_ceil(layer.tile_width) + LAYER_CACHE_MARGIN_TILES) *
+			layer.tile_width) - self.x;
+		self.height = (((scroll_y + height).div_ceil(layer.tile_height) + LAYER_CACHE_MARGIN_TILES) *
+			layer.tile_height) - self.y;
+	}
+
+	fn render(&mut self, clock: &TileAnimationClock, layer: &MapLayer,
+		tile_renderer: &Fn(&mut [u32], &[u8], usize, usize, Option<&[u32]>, &Fn(&mut u32, u32))) {
+		self.pixels.resize(self.width, self.height);
+		self.pixels.fill(LAYER_CACHE_TRANSPARENT);
+
+		let cache_bounds = BoundingRect {
+			x: 0,
+			y: 0,
+			width: self.width as isize,
+			height: self.height as isize
+		};
+		let tiles = LayerTiles::new(layer, clock, self.x, self.y, self.width, self.height);
+		render_layer_tiles(&cache_bounds, &mut self.pixels.band_mut(), &tiles, self.x, self.y, tile_renderer, &normal_blend);
+
+		self.animation_frames.clear();
+		for tile_ref in &self.animated_tiles {
+			self.animation_frames.push(clock.frame_for_tile(tile_ref, 0, 0));
+		}
+		self.trigger_frames.clear();
+		self.trigger_frames.extend(clock.trigger_frames(layer));
+		self.valid = true;
+	}
+
+	fn view(&self) -> LayerCacheView<'_> {
+		LayerCacheView {
+			pixels: &self.pixels,
+			x: self.x,
+			y: self.y
+		}
+	}
+}
+
+impl<'a> LayerCacheView<'a> {
+	fn blit(&self, bounds: &BoundingRect, render_buf: &mut FrameBand, scroll_x: usize, scroll_y: usize,
+		blend: &Fn(&mut u32, u32)) {
+		let offset_x = scroll_x - self.x;
+		let offset_y = scroll_y - self.y;
+		let width = bounds.width as usize;
+		for y in 0..bounds.height as usize {
+			let cache_row = self.pixels.row_span(offset_y + y, offset_x, width);
+			let render_buf_row = render_buf.row_span_mut(bounds.y as usize + y, bounds.x as usize, width);
+			for (pixel, color) in render_buf_row.iter_mut().zip(cache_row) {
+				if *color != LAYER_CACHE_TRANSPARENT {
+					blend(pixel, *color);
+				}
+			}
+		}
+	}
+}
+
+impl<'a> TileImage<'a> {
+	fn new(tile_ref: &'a TileRef, frame: usize) -> TileImage<'a> {
+		let palette = if tile_ref.palette_override.is_some() {
+			&tile_ref.palette_override
+		} else {
+			&tile_ref.tile_set.tiles[tile_ref.tile_index].palette
+		};
+		TileImage {
+			data: tile_ref.tile_set.data_for_frame(tile_ref.tile_index, frame),
+			palette: palette_entries(palette)
+		}
+	}
+}
+
+impl<'a> LayerTiles<'a> {
+	// Looks up the tiles of `layer` covering an area of the given size at a scroll position, along with
+	// their current animation frames
+	fn new(layer: &'a MapLayer, clock: &TileAnimationClock, scroll_x: usize, scroll_y: usize,
+		width: usize, height: usize) -> LayerTiles<'a> {
+		let left_tile = scroll_x / layer.tile_width;
+		let right_tile = (scroll_x + width - 1) / layer.tile_width;
+		let top_tile = scroll_y / layer.tile_height;
+		let bottom_tile = (scroll_y + height - 1) / layer.tile_height;
+		let columns = (right_tile - left_tile) + 1;
+
+		let mut tiles = Vec::with_capacity(columns * ((bottom_tile - top_tile) + 1));
+		for tile_y in top_tile ..= bottom_tile {
+			let map_y = tile_y % layer.height;
+			for tile_x in left_tile ..= right_tile {
+				let map_x = tile_x % layer.width;
+				let tile = layer.tiles[(map_y * layer.width) + map_x].as_ref()
+					.map(|tile_ref| TileImage::new(tile_ref, clock.frame_for_tile(tile_ref, map_x, map_y)));
+				tiles.push(tile);
+			}
+		}
+
+		LayerTiles {
+			tile_width: layer.tile_width,
+			tile_height: layer.tile_height,
+			tile_depth: layer.tile_depth,
+			left_tile, top_tile, columns, tiles
+		}
+	}
+
+	fn get(&self, tile_x: usize, tile_y: usize) -> Option<&TileImage<'a>> {
+		self.tiles[((tile_y - self.top_tile) * self.columns) + (tile_x - self.left_tile)].as_ref()
+	}
+}
+
+impl<'a> SpriteImage<'a> {
+	fn new(animation: &'a SpriteAnimation, t: usize) -> SpriteImage<'a> {
+		let frame = animation.frame_for_time(t);
+		let layout = &animation.frame_layouts[frame];
+		SpriteImage {
+			data: animation.data_for_frame(frame),
+			width: layout.width,
+			height: layout.height,
+			depth: animation.depth,
+			origin_x: layout.origin_x,
+			origin_y: layout.origin_y,
+			palette: palette_entries(&animation.palette)
+		}
+	}
+}
+
+fn palette_entries(palette: &Option<PaletteWithOffset>) -> Option<&[u32]> {
+	palette.as_ref().map(|palette| &palette.palette.entries[palette.offset..])
+}
+
 fn normal_blend(pixel: &mut u32, color: u32) {
 	*pixel = color;
 }
@@ -248,6 +887,57 @@ fn multiply_blend(pixel: &mut u32, color: u32) {
 	*pixel = ((blended_r << 16) | (blended_g << 8) | blended_b) & 0xf8f8f8;
 }
 
+fn blend_channels(pixel: &mut u32, color: u32, blend: &Fn(u32, u32) -> u32) {
+	let existing_color = *pixel;
+	let existing_r = (existing_color >> 16) & 0xff;
+	let existing_g = (existing_color >> 8) & 0xff;
+	let existing_b = existing_color & 0xff;
+
+	let blend_r = (color >> 16) & 0xff;
+	let blend_g = (color >> 8) & 0xff;
+	let blend_b = color & 0xff;
+
+	let blended_r = blend(existing_r, blend_r);
+	let blended_g = blend(existing_g, blend_g);
+	let blended_b = blend(existing_b, blend_b);
+
+	*pixel = ((blended_r << 16) | (blended_g << 8) | blended_b) & 0xf8f8f8;
+}
+
+fn screen_blend(pixel: &mut u32, color: u32) {
+	blend_channels(pixel, color, &|existing, screen| 0xff - (((0xff - existing) * (0xff - screen)) / 0xff));
+}
+
+fn overlay_blend(pixel: &mut u32, color: u32) {
+	blend_channels(pixel, color, &|existing, overlay| {
+		if existing < 0x80 {
+			(2 * existing * overlay) / 0xff
+		} else {
+			0xff - ((2 * (0xff - existing) * (0xff - overlay)) / 0xff)
+		}
+	});
+}
+
+fn difference_blend(pixel: &mut u32, color: u32) {
+	blend_channels(pixel, color, &|existing, diff| existing.abs_diff(diff));
+}
+
+fn lighten_blend(pixel: &mut u32, color: u32) {
+	blend_channels(pixel, color, &|existing, lighten| max(existing, lighten));
+}
+
+fn darken_blend(pixel: &mut u32, color: u32) {
+	blend_channels(pixel, color, &|existing, darken| min(existing, darken));
+}
+
+fn tint_blend(pixel: &mut u32, color: u32, tint_color: u32, amount: u8) {
+	// Mix source color towards the tint color, then write it like a normal blend
+	let mut tinted_color = color;
+	blend_channels(&mut tinted_color, tint_color, &|source, tint|
+		((source * (16 - amount as u32)) + (tint * amount as u32)) / 16);
+	*pixel = tinted_color;
+}
+
 fn alpha_blend(pixel: &mut u32, color: u32, alpha: u8, blend: &Fn(&mut u32, u32)) {
 	let existing_color = *pixel;
 	let mut mixed_color = existing_color;
@@ -268,10 +958,10 @@ fn alpha_blend(pixel: &mut u32, color: u32, alpha: u8, blend: &Fn(&mut u32, u32)
 	*pixel = ((blended_r << 16) | (blended_g << 8) | blended_b) & 0xf8f8f8;
 }
 
-fn render_tile_4bit(render_buf: &mut [u32], tile_data: &[u8], left: usize, width: usize, palette: &Option<PaletteWithOffset>,
+fn render_tile_4bit(render_buf: &mut [u32], tile_data: &[u8], left: usize, width: usize, palette: Option<&[u32]>,
 	blend: &Fn(&mut u32, u32)) {
 	let palette_entries = match palette {
-		Some(pal_with_offset) => &pal_with_offset.palette.entries[pal_with_offset.offset..],
+		Some(entries) => entries,
 		None => return
 	};
 	for i in 0..width {
@@ -284,10 +974,10 @@ fn render_tile_4bit(render_buf: &mut [u32], tile_data: &[u8], left: usize, width
 	}
 }
 
-fn render_tile_8bit(render_buf: &mut [u32], tile_data: &[u8], left: usize, width: usize, palette: &Option<PaletteWithOffset>,
+fn render_tile_8bit(render_buf: &mut [u32], tile_data: &[u8], left: usize, width: usize, palette: Option<&[u32]>,
 	blend: &Fn(&mut u32, u32)) {
 	let palette_entries = match palette {
-		Some(pal_with_offset) => &pal_with_offset.palette.entries[pal_with_offset.offset..],
+		Some(entries) => entries,
 		None => return
 	};
 	for i in 0..width {
@@ -300,7 +990,7 @@ fn render_tile_8bit(render_buf: &mut [u32], tile_data: &[u8], left: usize, width
 	}
 }
 
-fn render_tile_16bit(render_buf: &mut [u32], tile_data: &[u8], left: usize, width: usize, _palette: &Option<PaletteWithOffset>,
+fn render_tile_16bit(render_buf: &mut [u32], tile_data: &[u8], left: usize, width: usize, _palette: Option<&[u32]>,
 	blend: &Fn(&mut u32, u32)) {
 	for i in 0..width {
 		let x = left + i;
@@ -311,45 +1001,26 @@ fn render_tile_16bit(render_buf: &mut [u32], tile_data: &[u8], left: usize, widt
 	}
 }
 
-fn render_layer_with_blending(bounds: &BoundingRect, render_buf: &mut Vec<Vec<u32>>,
-	game: &GameState, layer: &MapLayer, scroll_x: isize, scroll_y: isize,
-	tile_renderer: &Fn(&mut [u32], &[u8], usize, usize, &Option<PaletteWithOffset>, &Fn(&mut u32, u32)),
+fn render_layer_tiles(bounds: &BoundingRect, render_buf: &mut FrameBand, tiles: &LayerTiles,
+	scroll_x: usize, scroll_y: usize,
+	tile_renderer: &Fn(&mut [u32], &[u8], usize, usize, Option<&[u32]>, &Fn(&mut u32, u32)),
 	blend: &Fn(&mut u32, u32)) {
-	if (layer.width == 0) || (layer.height == 0) {
-		return;
-	}
-
-	// Compute scrolling for this layer
-	let parallax_x = layer.parallax_x as isize;
-	let parallax_y = layer.parallax_y as isize;
-	let auto_scroll_x = layer.auto_scroll_x as isize;
-	let auto_scroll_y = layer.auto_scroll_y as isize;
-	let frame = game.frame as isize;
-	let bias_x = 0x40000000 - (0x40000000 % (layer.tile_width * layer.width)) as isize;
-	let bias_y = 0x40000000 - (0x40000000 % (layer.tile_height * layer.height)) as isize;
-	let scroll_x = (((scroll_x * parallax_x + auto_scroll_x * frame) / 0x100) + bias_x) as usize;
-	let scroll_y = (((scroll_y * parallax_y + auto_scroll_y * frame) / 0x100) + bias_y) as usize;
-
 	// Compute bounds of rendering
-	let left_tile = scroll_x / layer.tile_width;
-	let left_pixel = scroll_x % layer.tile_width;
-	let right_tile = (scroll_x + bounds.width as usize - 1) / layer.tile_width;
-	let right_pixel = (scroll_x + bounds.width as usize - 1) % layer.tile_width;
-	let top_tile = scroll_y / layer.tile_height;
-	let top_pixel = scroll_y % layer.tile_height;
-	let bottom_tile = (scroll_y + bounds.height as usize - 1) / layer.tile_height;
-	let bottom_pixel = (scroll_y + bounds.height as usize - 1) % layer.tile_height;
+	let left_tile = scroll_x / tiles.tile_width;
+	let left_pixel = scroll_x % tiles.tile_width;
+	let right_tile = (scroll_x + bounds.width as usize - 1) / tiles.tile_width;
+	let right_pixel = (scroll_x + bounds.width as usize - 1) % tiles.tile_width;
+	let top_tile = scroll_y / tiles.tile_height;
+	let top_pixel = scroll_y % tiles.tile_height;
+	let bottom_tile = (scroll_y + bounds.height as usize - 1) / tiles.tile_height;
+	let bottom_pixel = (scroll_y + bounds.height as usize - 1) % tiles.tile_height;
 
 	// Compute tile data layout
-	let tile_pitch = ((layer.tile_width * layer.tile_depth) + 7) / 8;
+	let tile_pitch = (tiles.tile_width * tiles.tile_depth).div_ceil(8);
 
 	// Render tiles
 	let mut target_y = 0;
 	for tile_y in top_tile ..= bottom_tile {
-		// Grab slice of map layer for this row of tiles
-		let map_row_index = tile_y % layer.height;
-		let map_row = &layer.tiles[map_row_index * layer.width .. (map_row_index + 1) * layer.width];
-
 		// Compute rendering extents for current row of tiles
 		let cur_top_pixel;
 		let cur_bottom_pixel;
@@ -361,22 +1032,12 @@ fn render_layer_with_blending(bounds: &BoundingRect, render_buf: &mut Vec<Vec<u3
 		if tile_y == bottom_tile {
 			cur_bottom_pixel = bottom_pixel;
 		} else {
-			cur_bottom_pixel = layer.tile_height - 1;
+			cur_bottom_pixel = tiles.tile_height - 1;
 		}
 
 		let mut target_x = 0;
 		for tile_x in left_tile ..= right_tile {
-			// Look up tile in map layer
-			let tile = &map_row[tile_x % layer.width];
-			if let Some(tile_ref) = tile {
-				// Grab tile data for the current animation frame, and get palette for tile
-				let tile_data = tile_ref.tile_set.data_for_time(tile_ref.tile_index, game.frame);
-				let palette = if tile_ref.palette_override.is_some() {
-					&tile_ref.palette_override
-				} else {
-					&tile_ref.tile_set.tiles[tile_ref.tile_index].palette
-				};
-
+			if let Some(tile) = tiles.get(tile_x, tile_y) {
 				// Compute rendering extents for current tile
 				let cur_left_pixel;
 				let cur_right_pixel;
@@ -388,318 +1049,690 @@ fn render_layer_with_blending(bounds: &BoundingRect, render_buf: &mut Vec<Vec<u3
 				if tile_x == right_tile {
 					cur_right_pixel = right_pixel;
 				} else {
-					cur_right_pixel = layer.tile_width - 1;
+					cur_right_pixel = tiles.tile_width - 1;
 				}
 
 				let tile_render_width = (cur_right_pixel - cur_left_pixel) + 1;
 
 				// Render tile
 				for pixel_y in cur_top_pixel ..= cur_bottom_pixel {
-					let tile_data_row = &tile_data[pixel_y * tile_pitch .. (pixel_y + 1) * tile_pitch];
-					let render_buf_row = &mut render_buf[(target_y + bounds.y as usize) + (pixel_y - cur_top_pixel)];
-					let render_buf_tile = &mut render_buf_row[target_x + bounds.x as usize ..
-						target_x + bounds.x as usize + tile_render_width];
-					tile_renderer(render_buf_tile, tile_data_row, cur_left_pixel, tile_render_width, palette, blend);
+					let tile_data_row = &tile.data[pixel_y * tile_pitch .. (pixel_y + 1) * tile_pitch];
+					let render_buf_tile = render_buf.row_span_mut((target_y + bounds.y as usize) + (pixel_y - cur_top_pixel),
+						target_x + bounds.x as usize, tile_render_width);
+					tile_renderer(render_buf_tile, tile_data_row, cur_left_pixel, tile_render_width, tile.palette, blend);
 				}
 			}
 
 			// Update render target x coordinate
 			if tile_x == left_tile {
-				target_x += layer.tile_width - left_pixel;
+				target_x += tiles.tile_width - left_pixel;
 			} else {
-				target_x += layer.tile_width;
+				target_x += tiles.tile_width;
 			}
 		}
 
 		// Update render target y coordinate
 		if tile_y == top_tile {
-			target_y += layer.tile_height - top_pixel;
+			target_y += tiles.tile_height - top_pixel;
 		} else {
-			target_y += layer.tile_height;
+			target_y += tiles.tile_height;
 		}
 	}
 }
 
-fn render_layer_with_renderer(bounds: &BoundingRect, render_buf: &mut Vec<Vec<u32>>,
-	game: &GameState, scroll_x: isize, scroll_y: isize, layer: &MapLayer,
-	tile_renderer: &Fn(&mut [u32], &[u8], usize, usize, &Option<PaletteWithOffset>, &Fn(&mut u32, u32))) {
-	match layer.alpha {
+fn layer_scroll(layer: &MapLayer, frame: usize, scroll_x: isize, scroll_y: isize) -> (usize, usize) {
+	// Compute scrolling for this layer
+	let parallax_x = layer.parallax_x as isize;
+	let parallax_y = layer.parallax_y as isize;
+	let auto_scroll_x = layer.auto_scroll_x as isize;
+	let auto_scroll_y = layer.auto_scroll_y as isize;
+	let frame = frame as isize;
+	let bias_x = 0x40000000 - (0x40000000 % (layer.tile_width * layer.width)) as isize;
+	let bias_y = 0x40000000 - (0x40000000 % (layer.tile_height * layer.height)) as isize;
+	let scroll_x = (((scroll_x * parallax_x + auto_scroll_x * frame) / 0x100) + bias_x) as usize;
+	let scroll_y = (((scroll_y * parallax_y + auto_scroll_y * frame) / 0x100) + bias_y) as usize;
+	(scroll_x, scroll_y)
+}
+
+fn render_layer_with_blending(bounds: &BoundingRect, render_buf: &mut FrameBand, source: &LayerSource,
+	scroll_x: usize, scroll_y: usize,
+	tile_renderer: &Fn(&mut [u32], &[u8], usize, usize, Option<&[u32]>, &Fn(&mut u32, u32)),
+	blend: &Fn(&mut u32, u32)) {
+	match source {
+		LayerSource::Cache(cache) => cache.blit(bounds, render_buf, scroll_x, scroll_y, blend),
+		LayerSource::Tiles(tiles) => render_layer_tiles(bounds, render_buf, tiles, scroll_x, scroll_y, tile_renderer, blend)
+	};
+}
+
+fn render_layer_with_renderer(bounds: &BoundingRect, render_buf: &mut FrameBand, source: &LayerSource,
+	scroll_x: usize, scroll_y: usize, blending: &Blending,
+	tile_renderer: &Fn(&mut [u32], &[u8], usize, usize, Option<&[u32]>, &Fn(&mut u32, u32))) {
+	match blending.alpha {
 		0 => {
-			match layer.blend_mode {
+			match blending.mode {
 				BlendMode::Normal =>
-					render_layer_with_blending(bounds, render_buf, game, layer,
+					render_layer_with_blending(bounds, render_buf, source,
 						scroll_x, scroll_y, tile_renderer, &normal_blend),
 				BlendMode::Add =>
-					render_layer_with_blending(bounds, render_buf, game, layer,
+					render_layer_with_blending(bounds, render_buf, source,
 						scroll_x, scroll_y, tile_renderer, &add_blend),
 				BlendMode::Subtract =>
-					render_layer_with_blending(bounds, render_buf, game, layer,
+					render_layer_with_blending(bounds, render_buf, source,
 						scroll_x, scroll_y, tile_renderer, &subtract_blend),
 				BlendMode::Multiply =>
-					render_layer_with_blending(bounds, render_buf, game, layer,
-						scroll_x, scroll_y, tile_renderer, &multiply_blend)
+					render_layer_with_blending(bounds, render_buf, source,
+						scroll_x, scroll_y, tile_renderer, &multiply_blend),
+				BlendMode::Screen =>
+					render_layer_with_blending(bounds, render_buf, source,
+						scroll_x, scroll_y, tile_renderer, &screen_blend),
+				BlendMode::Overlay =>
+					render_layer_with_blending(bounds, render_buf, source,
+						scroll_x, scroll_y, tile_renderer, &overlay_blend),
+				BlendMode::Difference =>
+					render_layer_with_blending(bounds, render_buf, source,
+						scroll_x, scroll_y, tile_renderer, &difference_blend),
+				BlendMode::Lighten =>
+					render_layer_with_blending(bounds, render_buf, source,
+						scroll_x, scroll_y, tile_renderer, &lighten_blend),
+				BlendMode::Darken =>
+					render_layer_with_blending(bounds, render_buf, source,
+						scroll_x, scroll_y, tile_renderer, &darken_blend),
+				BlendMode::Tint(tint_color, amount) =>
+					render_layer_with_blending(bounds, render_buf, source, scroll_x, scroll_y,
+						tile_renderer, &|pixel, color| tint_blend(pixel, color, tint_color, amount))
 			};
 		},
 		alpha => {
-			match layer.blend_mode {
+			match blending.mode {
 				BlendMode::Normal =>
-					render_layer_with_blending(bounds, render_buf, game, layer, scroll_x, scroll_y,
+					render_layer_with_blending(bounds, render_buf, source, scroll_x, scroll_y,
 						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &normal_blend)),
 				BlendMode::Add =>
-					render_layer_with_blending(bounds, render_buf, game, layer, scroll_x, scroll_y,
+					render_layer_with_blending(bounds, render_buf, source, scroll_x, scroll_y,
 						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &add_blend)),
 				BlendMode::Subtract =>
-					render_layer_with_blending(bounds, render_buf, game, layer, scroll_x, scroll_y,
+					render_layer_with_blending(bounds, render_buf, source, scroll_x, scroll_y,
 						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &subtract_blend)),
 				BlendMode::Multiply =>
-					render_layer_with_blending(bounds, render_buf, game, layer, scroll_x, scroll_y,
+					render_layer_with_blending(bounds, render_buf, source, scroll_x, scroll_y,
 						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &multiply_blend)),
+				BlendMode::Screen =>
+					render_layer_with_blending(bounds, render_buf, source, scroll_x, scroll_y,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &screen_blend)),
+				BlendMode::Overlay =>
+					render_layer_with_blending(bounds, render_buf, source, scroll_x, scroll_y,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &overlay_blend)),
+				BlendMode::Difference =>
+					render_layer_with_blending(bounds, render_buf, source, scroll_x, scroll_y,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &difference_blend)),
+				BlendMode::Lighten =>
+					render_layer_with_blending(bounds, render_buf, source, scroll_x, scroll_y,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &lighten_blend)),
+				BlendMode::Darken =>
+					render_layer_with_blending(bounds, render_buf, source, scroll_x, scroll_y,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &darken_blend)),
+				BlendMode::Tint(tint_color, amount) =>
+					render_layer_with_blending(bounds, render_buf, source, scroll_x, scroll_y,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha,
+							&|pixel, color| tint_blend(pixel, color, tint_color, amount))),
 			};
 		}
 	};
 }
 
-fn render_layer(bounds: &BoundingRect, render_buf: &mut Vec<Vec<u32>>, game: &GameState,
-	scroll_x: isize, scroll_y: isize, layer: &MapLayer) {
-	match layer.tile_depth {
-		4 => render_layer_with_renderer(bounds, render_buf, game, scroll_x, scroll_y, &layer, &render_tile_4bit),
-		8 => render_layer_with_renderer(bounds, render_buf, game, scroll_x, scroll_y, &layer, &render_tile_8bit),
-		16 => render_layer_with_renderer(bounds, render_buf, game, scroll_x, scroll_y, &layer, &render_tile_16bit),
-		_ => panic!("Invalid tile bit depth {}", layer.tile_depth)
+fn render_layer(bounds: &BoundingRect, render_buf: &mut FrameBand, source: &LayerSource,
+	scroll_x: usize, scroll_y: usize, tile_depth: usize, blending: &Blending) {
+	match tile_depth {
+		4 => render_layer_with_renderer(bounds, render_buf, source, scroll_x, scroll_y, blending, &render_tile_4bit),
+		8 => render_layer_with_renderer(bounds, render_buf, source, scroll_x, scroll_y, blending, &render_tile_8bit),
+		16 => render_layer_with_renderer(bounds, render_buf, source, scroll_x, scroll_y, blending, &render_tile_16bit),
+		_ => panic!("Invalid tile bit depth {}", tile_depth)
 	};
 }
 
-fn render_sprite_with_blending(render_size: &RenderSize, render_buf: &mut Vec<Vec<u32>>,
-	x: isize, y: isize, animation: &SpriteAnimation, frame: usize,
-	tile_renderer: &Fn(&mut [u32], &[u8], usize, usize, &Option<PaletteWithOffset>, &Fn(&mut u32, u32)),
+fn render_sprite_with_blending(render_buf: &mut FrameBand, clip: &BoundingRect,
+	x: isize, y: isize, image: &SpriteImage,
+	tile_renderer: &Fn(&mut [u32], &[u8], usize, usize, Option<&[u32]>, &Fn(&mut u32, u32)),
 	blend: &Fn(&mut u32, u32)) {
-	if (x >= render_size.width as isize) || (y >= render_size.height as isize) ||
-		(x <= -(animation.width as isize)) || (y <= -(animation.height as isize)) {
+	// Place the frame's origin at the sprite position
+	let x = x - image.origin_x;
+	let y = y - image.origin_y;
+
+	// Clip to the intersection of the clip rectangle and the buffer
+	let left = max(clip.x, 0);
+	let top = max(clip.y, 0);
+	let right = min(clip.x + clip.width, render_buf.width as isize);
+	let bottom = min(clip.y + clip.height, render_buf.height as isize);
+	if (x >= right) || (y >= bottom) ||
+		((x + image.width as isize) <= left) || ((y + image.height as isize) <= top) {
 		return;
 	}
 
-	let mut x_offset = 0;
-	let mut y_offset = 0;
-	let x_start;
-	let y_start;
-	let mut width = animation.width;
-	let mut height = animation.height;
-
-	if x < 0 {
-		x_offset = (-x) as usize;
-		width -= x_offset;
-		x_start = 0;
-	} else {
-		x_start = x as usize;
-	}
-
-	if y < 0 {
-		y_offset = (-y) as usize;
-		height -= y_offset;
-		y_start = 0;
-	} else {
-		y_start = y as usize;
-	}
-
-	if (x_start + width) > render_size.width {
-		width = render_size.width - x_start;
-	}
-
-	if (y_start + height) > render_size.height {
-		height = render_size.height - y_start;
-	}
+	let x_start = max(x, left);
+	let y_start = max(y, top);
+	let x_offset = (x_start - x) as usize;
+	let y_offset = (y_start - y) as usize;
+	let width = (min(x + image.width as isize, right) - x_start) as usize;
+	let height = (min(y + image.height as isize, bottom) - y_start) as usize;
+	let x_start = x_start as usize;
+	let y_start = y_start as usize;
 
-	let sprite_data = animation.data_for_time(frame);
-	let pitch = ((animation.width * animation.depth) + 7) / 8;
+	let pitch = (image.width * image.depth).div_ceil(8);
 
 	for pixel_y in 0..height {
-		let row_data = &sprite_data[(y_offset + pixel_y) * pitch .. (y_offset + pixel_y + 1) * pitch];
-		let render_buf_row = &mut render_buf[y_start + pixel_y];
-		let render_buf_tile = &mut render_buf_row[x_start .. x_start + width];
-		tile_renderer(render_buf_tile, row_data, x_offset, width, &animation.palette, &blend);
+		let row_data = &image.data[(y_offset + pixel_y) * pitch .. (y_offset + pixel_y + 1) * pitch];
+		let render_buf_tile = render_buf.row_span_mut(y_start + pixel_y, x_start, width);
+		tile_renderer(render_buf_tile, row_data, x_offset, width, image.palette, &blend);
 	}
 }
 
-fn render_sprite_with_renderer(render_size: &RenderSize, render_buf: &mut Vec<Vec<u32>>, x: isize, y: isize,
-	animation: &SpriteAnimation, frame: usize, blend_mode: &BlendMode, alpha: u8,
-	tile_renderer: &Fn(&mut [u32], &[u8], usize, usize, &Option<PaletteWithOffset>, &Fn(&mut u32, u32))) {
-	match alpha {
+fn render_sprite_with_renderer(render_buf: &mut FrameBand, clip: &BoundingRect, x: isize, y: isize,
+	image: &SpriteImage, blending: &Blending,
+	tile_renderer: &Fn(&mut [u32], &[u8], usize, usize, Option<&[u32]>, &Fn(&mut u32, u32))) {
+	match blending.alpha {
 		0 => {
-			match blend_mode {
+			match &blending.mode {
 				BlendMode::Normal =>
-					render_sprite_with_blending(render_size, render_buf, x, y, animation, frame,
+					render_sprite_with_blending(render_buf, clip, x, y, image,
 						tile_renderer, &normal_blend),
 				BlendMode::Add =>
-					render_sprite_with_blending(render_size, render_buf, x, y, animation, frame,
+					render_sprite_with_blending(render_buf, clip, x, y, image,
 						tile_renderer, &add_blend),
 				BlendMode::Subtract =>
-					render_sprite_with_blending(render_size, render_buf, x, y, animation, frame,
+					render_sprite_with_blending(render_buf, clip, x, y, image,
 						tile_renderer, &subtract_blend),
 				BlendMode::Multiply =>
-					render_sprite_with_blending(render_size, render_buf, x, y, animation, frame,
-						tile_renderer, &multiply_blend)
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &multiply_blend),
+				BlendMode::Screen =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &screen_blend),
+				BlendMode::Overlay =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &overlay_blend),
+				BlendMode::Difference =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &difference_blend),
+				BlendMode::Lighten =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &lighten_blend),
+				BlendMode::Darken =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &darken_blend),
+				BlendMode::Tint(tint_color, amount) =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &|pixel, color| tint_blend(pixel, color, *tint_color, *amount))
 			};
 		},
 		alpha => {
-			match blend_mode {
+			match &blending.mode {
 				BlendMode::Normal =>
-					render_sprite_with_blending(render_size, render_buf, x, y, animation, frame,
+					render_sprite_with_blending(render_buf, clip, x, y, image,
 						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &normal_blend)),
 				BlendMode::Add =>
-					render_sprite_with_blending(render_size, render_buf, x, y, animation, frame,
+					render_sprite_with_blending(render_buf, clip, x, y, image,
 						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &add_blend)),
 				BlendMode::Subtract =>
-					render_sprite_with_blending(render_size, render_buf, x, y, animation, frame,
+					render_sprite_with_blending(render_buf, clip, x, y, image,
 						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &subtract_blend)),
 				BlendMode::Multiply =>
-					render_sprite_with_blending(render_size, render_buf, x, y, animation, frame,
+					render_sprite_with_blending(render_buf, clip, x, y, image,
 						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &multiply_blend)),
+				BlendMode::Screen =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &screen_blend)),
+				BlendMode::Overlay =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &overlay_blend)),
+				BlendMode::Difference =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &difference_blend)),
+				BlendMode::Lighten =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &lighten_blend)),
+				BlendMode::Darken =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha, &darken_blend)),
+				BlendMode::Tint(tint_color, amount) =>
+					render_sprite_with_blending(render_buf, clip, x, y, image,
+						tile_renderer, &|pixel, color| alpha_blend(pixel, color, alpha,
+							&|pixel, color| tint_blend(pixel, color, *tint_color, *amount))),
 			};
 		}
 	};
 }
 
-fn render_sprite(render_size: &RenderSize, render_buf: &mut Vec<Vec<u32>>, x: isize, y: isize,
-	animation: &SpriteAnimation, frame: usize, blend_mode: &BlendMode, alpha: u8) {
-	match animation.depth {
-		4 => render_sprite_with_renderer(render_size, render_buf, x, y, animation, frame,
-			blend_mode, alpha, &render_tile_4bit),
-		8 => render_sprite_with_renderer(render_size, render_buf, x, y, animation, frame,
-			blend_mode, alpha, &render_tile_8bit),
-		16 => render_sprite_with_renderer(render_size, render_buf, x, y, animation, frame,
-			blend_mode, alpha, &render_tile_16bit),
-		_ => panic!("Invalid sprite bit depth {}", animation.depth)
+fn render_sprite(render_buf: &mut FrameBand, clip: &BoundingRect, x: isize, y: isize,
+	image: &SpriteImage, blending: &Blending) {
+	match image.depth {
+		4 => render_sprite_with_renderer(render_buf, clip, x, y, image,
+			blending, &render_tile_4bit),
+		8 => render_sprite_with_renderer(render_buf, clip, x, y, image,
+			blending, &render_tile_8bit),
+		16 => render_sprite_with_renderer(render_buf, clip, x, y, image,
+			blending, &render_tile_16bit),
+		_ => panic!("Invalid sprite bit depth {}", image.depth)
 	};
 }
 
-pub fn render_actors(render_size: &RenderSize, render_buf: &mut Vec<Vec<u32>>, game: &GameState) {
-	for actor in &game.actors {
-		let actor_ref = actor.borrow();
-		let actor_info = actor_ref.actor_info();
-		if !actor_info.destroyed {
-			for sprite in &actor_info.sprites {
-				if sprite.alpha < 16 {
-					render_sprite(render_size, render_buf, actor_info.x + sprite.x_offset - game.scroll_x,
-						actor_info.y + sprite.y_offset - game.scroll_y, &sprite.animation, sprite.animation_frame,
-						&sprite.blend_mode, sprite.alpha);
-				}
-			}
-		}
+fn add_layer_command<'a>(commands: &mut Vec<DrawCommand<'a>>, view: &LayerView, layer: &'a MapLayer,
+	animation: Option<&LayerAnimationState>, cache: Option<&'a LayerCache>) {
+	let bounds = &view.bounds;
+	if (layer.width == 0) || (layer.height == 0) || (bounds.width <= 0) || (bounds.height <= 0) {
+		return;
 	}
+
+	let (scroll_x, scroll_y) = layer_scroll(layer, view.frame, view.scroll_x, view.scroll_y);
+	let source = match cache {
+		Some(cache) => LayerSource::Cache(cache.view()),
+		None => {
+			let clock = match animation {
+				Some(animation) => TileAnimationClock::Layer(animation),
+				None => TileAnimationClock::Frame(view.frame)
+			};
+			LayerSource::Tiles(LayerTiles::new(layer, &clock, scroll_x, scroll_y,
+				bounds.width as usize, bounds.height as usize))
+		}
+	};
+	commands.push(DrawCommand::Layer {
+		bounds: bounds.clone(),
+		source, scroll_x, scroll_y,
+		tile_depth: layer.tile_depth,
+		blending: Blending {
+			mode: layer.blend_mode.clone(),
+			alpha: layer.alpha
+		}
+	});
 }
 
-pub fn render_frame(render_size: &RenderSize, render_buf: &mut Vec<Vec<u32>>, game: &GameState) {
-	let mut actors_rendered = false;
-	let full_bounds = BoundingRect {
-		x: 0,
-		y: 0,
-		width: render_size.width as isize,
-		height: render_size.height as isize
+fn place_actor_sprites<'a, 'b: 'a>(actors: &'a [Ref<'b, Box<Actor>>], map: Option<&Map>,
+	sort_mode: &DepthSortMode) -> Vec<PlacedSprite<'a>> {
+	let layer_count = match map {
+		Some(map) => map.layers.len(),
+		None => 0
+	};
+	let main_slot = match map.and_then(|map| map.main_layer) {
+		Some(main_layer) => main_layer + 1,
+		None => layer_count
 	};
 
-	if let Some(map) = &game.map {
-		// Fill initial frame with map's background color
-		let background_color = map.background_color;
-		for y in 0..render_size.height {
-			let row = &mut render_buf[y];
-			for x in 0..render_size.width {
-				row[x] = background_color;
+	let mut sprites = Vec::new();
+	for actor in actors {
+		let actor_info = actor.actor_info();
+		if actor_info.destroyed {
+			continue;
+		}
+		for sprite in &actor_info.sprites {
+			if sprite.alpha >= 16 {
+				continue;
 			}
+			let slot = match sprite.placement {
+				SpriteLayerPlacement::MainLayer => main_slot,
+				SpriteLayerPlacement::AboveLayer(layer) => layer + 1,
+				SpriteLayerPlacement::BelowLayer(layer) => layer
+			};
+			sprites.push(PlacedSprite {
+				slot: min(slot, layer_count),
+				actor_info, sprite
+			});
 		}
+	}
 
-		// Render each map layer
-		for (i, layer) in (&map.layers).into_iter().enumerate() {
-			render_layer(&full_bounds, render_buf, game, game.scroll_x, game.scroll_y, &layer);
+	// Sorting is stable, so sprites with equal keys stay in actor list order
+	match sort_mode {
+		DepthSortMode::Priority =>
+			sprites.sort_by_key(|placed| (placed.slot, placed.actor_info.priority, placed.sprite.priority)),
+		DepthSortMode::YPosition =>
+			sprites.sort_by_cached_key(|placed| (placed.slot, placed.actor_info.priority,
+				placed.actor_info.depth_sort_y(), placed.sprite.priority))
+	};
+	sprites
+}
 
-			if let Some(main_layer) = map.main_layer {
-				if i == main_layer {
-					render_actors(render_size, render_buf, game);
-					actors_rendered = true;
+fn add_actor_commands<'a>(commands: &mut Vec<DrawCommand<'a>>, sprites: &[PlacedSprite<'a>], view: &LayerView) {
+	for placed in sprites {
+		commands.push(DrawCommand::Sprite {
+			clip: view.bounds.clone(),
+			x: view.bounds.x + placed.actor_info.x + placed.sprite.x_offset - view.scroll_x,
+			y: view.bounds.y + placed.actor_info.y + placed.sprite.y_offset - view.scroll_y,
+			image: SpriteImage::new(&placed.sprite.animation, placed.sprite.animation_time()),
+			blending: Blending {
+				mode: placed.sprite.blend_mode.clone(),
+				alpha: placed.sprite.alpha
+			}
+		});
+	}
+}
+
+fn execute_draw_commands(commands: &[DrawCommand], render_buf: &mut FrameBand) {
+	let band_top = render_buf.y as isize;
+	let band_bottom = (render_buf.y + render_buf.height) as isize;
+
+	for command in commands {
+		match command {
+			DrawCommand::Fill(bounds, color) => {
+				// Clip fill to the rows covered by this band
+				let top = max(bounds.y, band_top);
+				let bottom = min(bounds.y + bounds.height, band_bottom);
+				for y in top..bottom {
+					render_buf.row_span_mut((y - band_top) as usize, bounds.x as usize, bounds.width as usize).fill(*color);
+				}
+			},
+			DrawCommand::Layer { bounds, source, scroll_x, scroll_y, tile_depth, blending } => {
+				// Clip layer to the rows covered by this band
+				let top = max(bounds.y, band_top);
+				let bottom = min(bounds.y + bounds.height, band_bottom);
+				if top >= bottom {
+					continue;
+				}
+				let band_bounds = BoundingRect {
+					x: bounds.x,
+					y: top - band_top,
+					width: bounds.width,
+					height: bottom - top
+				};
+				render_layer(&band_bounds, render_buf, source, *scroll_x, *scroll_y + (top - bounds.y) as usize,
+					*tile_depth, blending);
+			},
+			DrawCommand::Sprite { clip, x, y, image, blending } => {
+				let band_clip = BoundingRect {
+					x: clip.x,
+					y: clip.y - band_top,
+					width: clip.width,
+					height: clip.height
+				};
+				render_sprite(render_buf, &band_clip, *x, *y - band_top, image, blending);
+			},
+			DrawCommand::Fade(alpha) => {
+				for y in 0..render_buf.height {
+					for pixel in render_buf.row_mut(y) {
+						let color = *pixel;
+						let r = (color >> 16) & 0xff;
+						let g = (color >> 8) & 0xff;
+						let b = color & 0xff;
+						let blended_r = (r * (16 - *alpha as u32)) / 16;
+						let blended_g = (g * (16 - *alpha as u32)) / 16;
+						let blended_b = (b * (16 - *alpha as u32)) / 16;
+						*pixel = ((blended_r << 16) | (blended_g << 8) | blended_b) & 0xf8f8f8;
+					}
 				}
 			}
 		}
+	}
+}
+
+fn execute_draw_commands_in_bands(commands: &[DrawCommand], render_buf: &mut FrameBuffer, thread_pool: &ThreadPool) {
+	let band_rows = render_buf.height.div_ceil(thread_pool.current_num_threads());
+	if band_rows == 0 {
+		return;
+	}
+
+	// Each thread renders directly into its own rows of the frame
+	let bands = render_buf.bands_mut(band_rows);
+	thread_pool.scope(|scope| {
+		for mut band in bands {
+			scope.spawn(move |_| execute_draw_commands(commands, &mut band));
+		}
+	});
+}
+
+fn add_map_commands<'a, 'b: 'a>(commands: &mut Vec<DrawCommand<'a>>, view: &LayerView, map: Option<&'a Map>,
+	actors: &'a [Ref<'b, Box<Actor>>], sort_mode: &DepthSortMode, layer_animations: &'a [LayerAnimationState],
+	layer_caches: &'a [LayerCache]) {
+	let sprites = place_actor_sprites(actors, map, sort_mode);
+	let mut next_sprite = 0;
+
+	if let Some(map) = map {
+		// Fill initial frame with map's background color
+		commands.push(DrawCommand::Fill(view.bounds.clone(), map.background_color));
+
+		// Render each map layer, using the pre-rendered layer caches if enabled. Actor sprites placed
+		// below a layer are rendered before it.
+		for (i, layer) in map.layers.iter().enumerate() {
+			let count = sprites[next_sprite..].iter().take_while(|placed| placed.slot == i).count();
+			add_actor_commands(commands, &sprites[next_sprite .. next_sprite + count], view);
+			next_sprite += count;
+
+			add_layer_command(commands, view, layer, layer_animations.get(i), layer_caches.get(i));
+		}
 	} else {
 		// No map, fill with black
-		for y in 0..render_size.height {
-			let row = &mut render_buf[y];
-			for x in 0..render_size.width {
-				row[x] = 0;
+		commands.push(DrawCommand::Fill(view.bounds.clone(), 0));
+	}
+
+	// Remaining sprites are above all layers
+	add_actor_commands(commands, &sprites[next_sprite..], view);
+}
+
+fn add_ui_commands<'a, 'b: 'a>(commands: &mut Vec<DrawCommand<'a>>, ui_layers: &'a [Ref<'b, UILayer>],
+	clip: &BoundingRect, frame: usize) {
+	for layer in ui_layers {
+		let mut bounds = layer.get_window_rect();
+		let map_layer = layer.get_map_layer();
+		let mut scroll_x = 0;
+		let mut scroll_y = 0;
+		if (bounds.x >= (clip.x + clip.width)) || (bounds.y >= (clip.y + clip.height)) {
+			continue;
+		}
+		if bounds.x < clip.x {
+			scroll_x = clip.x - bounds.x;
+			if scroll_x >= bounds.width {
+				continue;
+			}
+			bounds.x = clip.x;
+			bounds.width -= scroll_x;
+		}
+		if bounds.y < clip.y {
+			scroll_y = clip.y - bounds.y;
+			if scroll_y >= bounds.height {
+				continue;
 			}
+			bounds.y = clip.y;
+			bounds.height -= scroll_y;
+		}
+		if (bounds.x + bounds.width) > (clip.x + clip.width) {
+			bounds.width = (clip.x + clip.width) - bounds.x;
+		}
+		if (bounds.y + bounds.height) > (clip.y + clip.height) {
+			bounds.height = (clip.y + clip.height) - bounds.y;
+		}
+		let view = LayerView { bounds, frame, scroll_x, scroll_y };
+		add_layer_command(commands, &view, map_layer, None, None);
+
+		for sprite in &layer.contents.sprites {
+			commands.push(DrawCommand::Sprite {
+				clip: clip.clone(),
+				x: view.bounds.x + sprite.x - scroll_x,
+				y: view.bounds.y + sprite.y - scroll_y,
+				image: SpriteImage::new(&sprite.animation, frame),
+				blending: Blending {
+					mode: sprite.blend_mode.clone(),
+					alpha: sprite.alpha
+				}
+			});
 		}
 	}
+}
+
+fn update_layer_caches(layer_caches: &mut Vec<LayerCache>, game: &GameState, bounds: &BoundingRect,
+	scroll_x: isize, scroll_y: isize) {
+	if let Some(map) = &*game.map.borrow() {
+		if game.layer_caching {
+			layer_caches.resize(map.layers.len(), LayerCache::new());
+		} else {
+			layer_caches.clear();
+		}
+
+		if (bounds.width <= 0) || (bounds.height <= 0) {
+			return;
+		}
 
-	if !actors_rendered {
-		render_actors(render_size, render_buf, game);
+		let layer_animations = game.tile_animations.borrow();
+		for (i, (layer, cache)) in map.layers.iter().zip(layer_caches.iter_mut()).enumerate() {
+			let clock = match layer_animations.get(i) {
+				Some(animation) => TileAnimationClock::Layer(animation),
+				None => TileAnimationClock::Frame(game.frame)
+			};
+			cache.validate(layer, &clock);
+			if (layer.width != 0) && (layer.height != 0) {
+				let (scroll_x, scroll_y) = layer_scroll(layer, game.frame, scroll_x, scroll_y);
+				cache.update(&clock, layer, scroll_x, scroll_y, bounds.width as usize, bounds.height as usize);
+			}
+		}
 	}
+}
+
+fn update_ui_layouts(layouts: &[UILayoutRef], bounds: &BoundingRect, game: &GameState, ui_layers: &mut Vec<UILayerRef>) {
+	for layout in layouts {
+		layout.borrow_mut().tick(game);
+		layout.borrow().update(bounds);
 
-	for layout in &game.ui_layouts {
-		let rect = BoundingRect {
+		for layer_ref in layout.borrow().layers() {
+			layer_ref.borrow_mut().update(game);
+			ui_layers.push(layer_ref);
+		}
+	}
+}
+
+pub fn render_actors(render_buf: &mut FrameBuffer, game: &GameState) {
+	let view = LayerView {
+		bounds: BoundingRect {
 			x: 0,
 			y: 0,
-			width: render_size.width as isize,
-			height: render_size.height as isize
+			width: render_buf.width as isize,
+			height: render_buf.height as isize
+		},
+		frame: game.frame,
+		scroll_x: game.scroll_x,
+		scroll_y: game.scroll_y
+	};
+	let actors: Vec<Ref<Box<Actor>>> = game.actors.iter().map(|actor| actor.borrow()).collect();
+	let sprites = place_actor_sprites(&actors, None, &game.depth_sort_mode);
+	let mut commands = Vec::new();
+	add_actor_commands(&mut commands, &sprites, &view);
+	execute_draw_commands(&commands, &mut render_buf.band_mut());
+}
+
+// Renders a view of a map and set of actors into an offscreen buffer, independent of the game's own
+// view. UI layouts and fades are not applied. The result can be turned into a sprite or map layer with
+// `FrameBuffer::to_sprite_animation` and `FrameBuffer::to_map_layer`.
+pub fn render_view(render_buf: &mut FrameBuffer, map: Option<&Map>, actors: &[ActorRef],
+	sort_mode: &DepthSortMode, frame: usize, scroll_x: isize, scroll_y: isize) {
+	let bounds = BoundingRect {
+		x: 0,
+		y: 0,
+		width: render_buf.width as isize,
+		height: render_buf.height as isize
+	};
+	if (bounds.width == 0) || (bounds.height == 0) {
+		return;
+	}
+
+	let actors: Vec<Ref<Box<Actor>>> = actors.iter().map(|actor| actor.borrow()).collect();
+	let mut commands = Vec::new();
+	let view = LayerView { bounds, frame, scroll_x, scroll_y };
+	add_map_commands(&mut commands, &view, map, &actors, sort_mode, &[], &[]);
+	execute_draw_commands(&commands, &mut render_buf.band_mut());
+}
+
+pub fn render_frame(render_buf: &mut FrameBuffer, game: &GameState) {
+	let render_size = render_buf.size();
+	let full_bounds = BoundingRect {
+		x: 0,
+		y: 0,
+		width: render_size.width as isize,
+		height: render_size.height as isize
+	};
+
+	// Update UI layouts and layer contents, both for the whole screen and for each viewport. Viewports
+	// are not borrowed while layouts tick, so that layouts can inspect them.
+	let mut ui_layers = Vec::new();
+	update_ui_layouts(&game.ui_layouts, &full_bounds, game, &mut ui_layers);
+
+	let mut viewport_ui_layers = Vec::new();
+	for viewport in &game.viewports {
+		let (layouts, bounds) = {
+			let viewport = viewport.borrow();
+			(viewport.ui_layouts.clone(), viewport.screen_rect(&render_size))
 		};
-		layout.borrow_mut().tick(game);
-		layout.borrow().update(&rect);
-
-		let layers = layout.borrow().layers();
-
-		for layer_ref in layers {
-			let mut layer = layer_ref.borrow_mut();
-			layer.update(game);
-			let mut bounds = layer.get_window_rect();
-			let map_layer = layer.get_map_layer();
-			let mut scroll_x = 0;
-			let mut scroll_y = 0;
-			if (bounds.x >= (render_size.width as isize)) || (bounds.y >= (render_size.height as isize)) {
-				continue;
-			}
-			if bounds.x < 0 {
-				scroll_x = -bounds.x;
-				if scroll_x >= bounds.width {
-					continue;
-				}
-				bounds.x = 0;
-				bounds.width -= scroll_x;
-			}
-			if bounds.y < 0 {
-				scroll_y = -bounds.y;
-				if scroll_y >= bounds.height {
-					continue;
-				}
-				bounds.y = 0;
-				bounds.height -= scroll_y;
-			}
-			if (bounds.x + bounds.width) > (render_size.width as isize) {
-				bounds.width = render_size.width as isize - bounds.x;
-			}
-			if (bounds.y + bounds.height) > (render_size.height as isize) {
-				bounds.height = render_size.height as isize - bounds.y;
-			}
-			render_layer(&bounds, render_buf, game, scroll_x, scroll_y, map_layer);
+		let mut layers = Vec::new();
+		update_ui_layouts(&layouts, &bounds, game, &mut layers);
+		viewport_ui_layers.push(layers);
+	}
+
+	// Bring map layer caches up to date, as draw commands can only read from them
+	let mut layer_caches = game.layer_caches.borrow_mut();
+	let mut viewports: Vec<RefMut<Viewport>> = game.viewports.iter().map(|viewport| viewport.borrow_mut()).collect();
+	if viewports.is_empty() {
+		update_layer_caches(&mut layer_caches, game, &full_bounds, game.scroll_x, game.scroll_y);
+	} else {
+		for viewport in &mut viewports {
+			let bounds = viewport.screen_rect(&render_size);
+			let (scroll_x, scroll_y) = (viewport.scroll_x, viewport.scroll_y);
+			update_layer_caches(&mut viewport.layer_caches, game, &bounds, scroll_x, scroll_y);
+		}
+	}
+
+	// Record draw commands for the frame
+	let layer_caches = &*layer_caches;
+	let ui_layers: Vec<Ref<UILayer>> = ui_layers.iter().map(|layer| layer.borrow()).collect();
+	let viewport_ui_layers: Vec<Vec<Ref<UILayer>>> = viewport_ui_layers.iter()
+		.map(|layers| layers.iter().map(|layer| layer.borrow()).collect()).collect();
+	let actors: Vec<Ref<Box<Actor>>> = game.actors.iter().map(|actor| actor.borrow()).collect();
+	let layer_animations = game.tile_animations.borrow();
+	let map = game.map.borrow();
+	let mut commands = Vec::new();
+
+	if viewports.is_empty() {
+		let view = LayerView {
+			bounds: full_bounds.clone(),
+			frame: game.frame,
+			scroll_x: game.scroll_x,
+			scroll_y: game.scroll_y
+		};
+		add_map_commands(&mut commands, &view, map.as_ref(), &actors, &game.depth_sort_mode, &layer_animations,
+			layer_caches);
+	} else {
+		// Areas not covered by a viewport are left black
+		commands.push(DrawCommand::Fill(full_bounds.clone(), 0));
 
-			for sprite in &layer.contents.sprites {
-				render_sprite(render_size, render_buf, bounds.x + sprite.x - scroll_x,
-					bounds.y + sprite.y - scroll_y, &sprite.animation, game.frame,
-					&sprite.blend_mode, sprite.alpha);
+		for (viewport, viewport_ui_layers) in viewports.iter().zip(&viewport_ui_layers) {
+			let bounds = viewport.screen_rect(&render_size);
+			if (bounds.width <= 0) || (bounds.height <= 0) {
+				continue;
 			}
+			let view = LayerView {
+				bounds,
+				frame: game.frame,
+				scroll_x: viewport.scroll_x,
+				scroll_y: viewport.scroll_y
+			};
+			add_map_commands(&mut commands, &view, map.as_ref(), &actors, &game.depth_sort_mode, &layer_animations,
+				&viewport.layer_caches);
+			add_ui_commands(&mut commands, viewport_ui_layers, &view.bounds, game.frame);
 		}
 	}
 
+	add_ui_commands(&mut commands, &ui_layers, &full_bounds, game.frame);
+
 	if game.fade_alpha > 0 {
 		// Full screen fade effect is in place
-		for y in 0..render_size.height {
-			let row = &mut render_buf[y];
-			for x in 0..render_size.width {
-				let color = row[x];
-				let r = (color >> 16) & 0xff;
-				let g = (color >> 8) & 0xff;
-				let b = color & 0xff;
-				let blended_r = (r * (16 - game.fade_alpha as u32)) / 16;
-				let blended_g = (g * (16 - game.fade_alpha as u32)) / 16;
-				let blended_b = (b * (16 - game.fade_alpha as u32)) / 16;
-				row[x] = ((blended_r << 16) | (blended_g << 8) | blended_b) & 0xf8f8f8;
-			}
+		commands.push(DrawCommand::Fade(game.fade_alpha));
+	}
+
+	// Render the frame, split into horizontal bands if multiple threads are requested. Each band runs
+	// the full command list clipped to its rows, so the output is identical to serial rendering.
+	if game.render_threads > 1 {
+		// Worker threads are kept between frames, and only recreated when the thread count changes
+		let mut thread_pool = game.render_thread_pool.borrow_mut();
+		if thread_pool.as_ref().is_none_or(|pool| pool.current_num_threads() != game.render_threads) {
+			*thread_pool = ThreadPoolBuilder::new().num_threads(game.render_threads).build().ok();
+		}
+		match thread_pool.as_ref() {
+			Some(thread_pool) => execute_draw_commands_in_bands(&commands, render_buf, thread_pool),
+			None => execute_draw_commands(&commands, &mut render_buf.band_mut())
 		}
+	} else {
+		execute_draw_commands(&commands, &mut render_buf.band_mut());
 	}
 }
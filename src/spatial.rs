@@ -0,0 +1,438 @@
+use std::rc::Rc;
+use std::cmp::{min, max};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use actor::{Actor, ActorRef, BoundingRect};
+
+// Default width and height of grid cells in pixels
+pub const DEFAULT_CELL_SIZE: isize = 64;
+
+struct SpatialEntry {
+	actor: ActorRef,
+	bounds: BoundingRect
+}
+
+// Closest actor hit by a ray, along with the point where the ray enters its collision bounds
+pub struct ActorRaycastHit {
+	pub actor: ActorRef,
+	pub x: isize,
+	pub y: isize
+}
+
+// Uniform grid of actor collision bounds, rebuilt every frame to find collision candidates without
+// checking every actor. The game updates an actor's entry after it ticks, and after a platform moves
+// it. Actors that are moved directly by other code must be updated with `update` to be found at their
+// new position.
+pub struct SpatialGrid {
+	pub cell_size: isize,
+	entries: Vec<SpatialEntry>,
+	cells: HashMap<(isize, isize), Vec<usize>>,
+	indices: HashMap<*const RefCell<Box<Actor>>, usize>
+}
+
+impl SpatialGrid {
+	pub fn new(cell_size: isize) -> SpatialGrid {
+		SpatialGrid {
+			cell_size: max(cell_size, 1),
+			entries: Vec::new(),
+			cells: HashMap::new(),
+			indices: HashMap::new()
+		}
+	}
+
+	pub fn clear(&mut self) {
+		self.entries.clear();
+		self.cells.clear();
+		self.indices.clear();
+	}
+
+	fn cell_range(&self, rect: &BoundingRect) -> (isize, isize, isize, isize) {
+		let left = rect.x.div_euclid(self.cell_size);
+		let top = rect.y.div_euclid(self.cell_size);
+		let right = (rect.x + max(rect.width, 1) - 1).div_euclid(self.cell_size);
+		let bottom = (rect.y + max(rect.height, 1) - 1).div_euclid(self.cell_size);
+		(left, top, right, bottom)
+	}
+
+	fn add_to_cells(&mut self, index: usize) {
+		let (left, top, right, bottom) = self.cell_range(&self.entries[index].bounds);
+		for cell_y in top ..= bottom {
+			for cell_x in left ..= right {
+				self.cells.entry((cell_x, cell_y)).or_default().push(index);
+			}
+		}
+	}
+
+	fn remove_from_cells(&mut self, index: usize) {
+		let (left, top, right, bottom) = self.cell_range(&self.entries[index].bounds);
+		for cell_y in top ..= bottom {
+			for cell_x in left ..= right {
+				if let Some(cell) = self.cells.get_mut(&(cell_x, cell_y)) {
+					cell.retain(|entry| *entry != index);
+				}
+			}
+		}
+	}
+
+	pub fn insert(&mut self, actor: &ActorRef, bounds: BoundingRect) {
+		if self.indices.contains_key(&Rc::as_ptr(actor)) {
+			self.update(actor, Some(bounds));
+			return;
+		}
+		let index = self.entries.len();
+		self.entries.push(SpatialEntry {
+			actor: actor.clone(),
+			bounds
+		});
+		self.indices.insert(Rc::as_ptr(actor), index);
+		self.add_to_cells(index);
+	}
+
+	// Moves an actor's entry to new collision bounds, adding the actor if it is not in the grid yet.
+	// Actors without bounds are no longer found.
+	pub fn update(&mut self, actor: &ActorRef, bounds: Option<BoundingRect>) {
+		let index = match self.indices.get(&Rc::as_ptr(actor)) {
+			Some(index) => *index,
+			None => {
+				if let Some(bounds) = bounds {
+					self.insert(actor, bounds);
+				}
+				return;
+			}
+		};
+
+		self.remove_from_cells(index);
+		match bounds {
+			Some(bounds) => {
+				self.entries[index].bounds = bounds;
+				self.add_to_cells(index);
+			},
+			None => {
+				// Keep the entry so that indices stay valid, but leave it out of every cell
+				self.entries[index].bounds = BoundingRect { x: 0, y: 0, width: 0, height: 0 };
+			}
+		}
+	}
+
+	// Replaces the contents of the grid with the actors that have collision bounds
+	pub fn rebuild(&mut self, actors: &[ActorRef]) {
+		self.clear();
+		for actor_ref in actors {
+			let bounds = actor_ref.borrow().actor_info().world_collision_bounds();
+			if let Some(bounds) = bounds {
+				self.insert(actor_ref, bounds);
+			}
+		}
+	}
+
+	// Actors whose grid entries overlap the rectangle, each reported once
+	fn candidates(&self, rect: &BoundingRect) -> Vec<&ActorRef> {
+		let (left, top, right, bottom) = self.cell_range(rect);
+		let cell_count = ((right - left) as usize + 1).saturating_mul((bottom - top) as usize + 1);
+		if cell_count > self.cells.len() {
+			// The rectangle spans more cells than are occupied, so checking every entry is cheaper
+			return self.entries.iter()
+				.filter(|entry| SpatialGrid::entry_overlaps(&entry.bounds, rect))
+				.map(|entry| &entry.actor)
+				.collect();
+		}
+
+		let mut indices = Vec::new();
+		for cell_y in top ..= bottom {
+			for cell_x in left ..= right {
+				if let Some(cell) = self.cells.get(&(cell_x, cell_y)) {
+					for index in cell {
+						if SpatialGrid::entry_overlaps(&self.entries[*index].bounds, rect) {
+							indices.push(*index);
+						}
+					}
+				}
+			}
+		}
+		indices.sort_unstable();
+		indices.dedup();
+		indices.iter().map(|index| &self.entries[*index].actor).collect()
+	}
+
+	// Entries of actors that lost their bounds are empty and never overlap anything
+	fn entry_overlaps(bounds: &BoundingRect, rect: &BoundingRect) -> bool {
+		(bounds.width > 0) && (bounds.height > 0) && bounds.is_colliding(rect)
+	}
+
+	// Cells crossed by the line from (`x0`, `y0`) to (`x1`, `y1`), grouped by column, or by row for
+	// mostly vertical lines. Groups are in order from the start of the line, and each comes with the
+	// fraction of the line that has been covered once the ray leaves the group.
+	fn ray_cells(&self, x0: isize, y0: isize, x1: isize, y1: isize) -> Vec<(f32, Vec<(isize, isize)>)> {
+		// Walk along the major axis, with coordinates swapped for mostly vertical lines
+		let steep = (y1 - y0).abs() > (x1 - x0).abs();
+		let (a0, b0, a1, b1) = if steep { (y0, x0, y1, x1) } else { (x0, y0, x1, y1) };
+		let step = if a1 >= a0 { 1 } else { -1 };
+		let length = (a1 - a0) as f64;
+		let slope = if a1 != a0 { (b1 - b0) as f64 / length } else { 0.0 };
+		let last = a1.div_euclid(self.cell_size);
+
+		let mut groups = Vec::new();
+		let mut major = a0.div_euclid(self.cell_size);
+		loop {
+			// Part of the line within this column, and the minor axis cells it passes through
+			let low = max(major * self.cell_size, min(a0, a1));
+			let high = min((major + 1) * self.cell_size, max(a0, a1));
+			let b_low = b0 as f64 + ((low - a0) as f64 * slope);
+			let b_high = b0 as f64 + ((high - a0) as f64 * slope);
+			let minor_first = (b_low.min(b_high).floor() as isize).div_euclid(self.cell_size);
+			let minor_last = (b_low.max(b_high).floor() as isize).div_euclid(self.cell_size);
+			let cells = (minor_first ..= minor_last)
+				.map(|minor| if steep { (minor, major) } else { (major, minor) })
+				.collect();
+
+			let exit = if step > 0 { (major + 1) * self.cell_size } else { major * self.cell_size };
+			let passed = if a1 != a0 { ((exit - a0) as f64 / length).min(1.0) } else { 1.0 };
+			groups.push((passed as f32, cells));
+
+			if major == last {
+				return groups;
+			}
+			major += step;
+		}
+	}
+
+	// Current collision bounds of an actor, if it can be borrowed, is not destroyed and is on the channel
+	fn live_bounds(actor_ref: &ActorRef, channel: Option<u32>) -> Option<BoundingRect> {
+		let actor = match actor_ref.try_borrow() {
+			Ok(actor) => actor,
+			Err(_) => return None
+		};
+		let actor_info = actor.actor_info();
+		if actor_info.destroyed {
+			return None;
+		}
+		if let Some(channel) = channel {
+			if actor_info.collision_channel != channel {
+				return None;
+			}
+		}
+		actor_info.world_collision_bounds()
+	}
+
+	// Actors with collision bounds overlapping the rectangle. Actors that are currently borrowed, such as
+	// the one making the query, are not included.
+	pub fn actors_in_rect(&self, rect: &BoundingRect, channel: Option<u32>) -> Vec<ActorRef> {
+		let mut actors = Vec::new();
+		for actor_ref in self.candidates(rect) {
+			if let Some(bounds) = SpatialGrid::live_bounds(actor_ref, channel) {
+				if bounds.is_colliding(rect) {
+					actors.push(actor_ref.clone());
+				}
+			}
+		}
+		actors
+	}
+
+	// Actors with collision bounds within `radius` pixels of a point
+	pub fn actors_in_radius(&self, x: isize, y: isize, radius: isize, channel: Option<u32>) -> Vec<ActorRef> {
+		let search = BoundingRect {
+			x: x - radius,
+			y: y - radius,
+			width: (radius * 2) + 1,
+			height: (radius * 2) + 1
+		};
+
+		let mut actors = Vec::new();
+		for actor_ref in self.candidates(&search) {
+			if let Some(bounds) = SpatialGrid::live_bounds(actor_ref, channel) {
+				// Distance from the point to the closest point of the bounds
+				let dx = x - min(max(x, bounds.x), bounds.x + bounds.width - 1);
+				let dy = y - min(max(y, bounds.y), bounds.y + bounds.height - 1);
+				if ((dx * dx) + (dy * dy)) <= (radius * radius) {
+					actors.push(actor_ref.clone());
+				}
+			}
+		}
+		actors
+	}
+
+	// Finds the first actor whose collision bounds are crossed by the line from (`x0`, `y0`) to
+	// (`x1`, `y1`)
+	pub fn raycast(&self, x0: isize, y0: isize, x1: isize, y1: isize, channel: Option<u32>) -> Option<ActorRaycastHit> {
+		let mut closest: Option<(f32, &ActorRef)> = None;
+		let mut check = |actor_ref| {
+			if let Some(bounds) = SpatialGrid::live_bounds(actor_ref, channel) {
+				if let Some(t) = bounds.ray_intersection(x0, y0, x1, y1) {
+					let closer = match closest {
+						Some((closest_t, _)) => t < closest_t,
+						None => true
+					};
+					if closer {
+						closest = Some((t, actor_ref));
+					}
+				}
+			}
+			closest.map(|(t, _)| t)
+		};
+
+		let crossed_cells = ((x1 - x0).abs() / self.cell_size) + ((y1 - y0).abs() / self.cell_size) + 2;
+		if crossed_cells as usize > self.cells.len() {
+			// The ray crosses more cells than are occupied, so checking every entry is cheaper
+			for entry in &self.entries {
+				if (entry.bounds.width > 0) && (entry.bounds.height > 0) {
+					check(&entry.actor);
+				}
+			}
+		} else {
+			let mut checked = HashSet::new();
+			let mut closest_t = None;
+			for (passed, cells) in self.ray_cells(x0, y0, x1, y1) {
+				for cell in cells {
+					if let Some(cell) = self.cells.get(&cell) {
+						for index in cell {
+							if checked.insert(*index) {
+								closest_t = check(&self.entries[*index].actor);
+							}
+						}
+					}
+				}
+
+				// Actors in later cells are hit further along the ray
+				if closest_t.is_some_and(|t| t <= passed) {
+					break;
+				}
+			}
+		}
+
+		closest.map(|(t, actor_ref)| ActorRaycastHit {
+			actor: actor_ref.clone(),
+			x: x0 + ((x1 - x0) as f32 * t).round() as isize,
+			y: y0 + ((y1 - y0) as f32 * t).round() as isize
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::rc::Rc;
+	use std::slice;
+	use actor::{ActorRef, BoundingRect};
+	use actor::tests::test_actor as sized_test_actor;
+	use super::SpatialGrid;
+
+	fn test_actor(x: isize, y: isize, channel: u32) -> ActorRef {
+		let actor = sized_test_actor(x, y, 16, 16);
+		actor.borrow_mut().actor_info_mut().collision_channel = channel;
+		actor
+	}
+
+	fn rect(x: isize, y: isize, width: isize, height: isize) -> BoundingRect {
+		BoundingRect { x, y, width, height }
+	}
+
+	#[test]
+	fn rect_query_finds_overlapping_actors() {
+		let near = test_actor(10, 10, 0);
+		let far = test_actor(300, 300, 0);
+		let mut grid = SpatialGrid::new(64);
+		grid.rebuild(&[near.clone(), far.clone()]);
+
+		let found = grid.actors_in_rect(&rect(0, 0, 20, 20), None);
+		assert_eq!(found.len(), 1);
+		assert!(Rc::ptr_eq(&found[0], &near));
+
+		// Touching edges do not overlap
+		assert!(grid.actors_in_rect(&rect(26, 10, 10, 10), None).is_empty());
+
+		// Bounds spanning several cells are reported once
+		assert_eq!(grid.actors_in_rect(&rect(0, 0, 400, 400), None).len(), 2);
+	}
+
+	#[test]
+	fn rect_query_filters_channel_and_borrowed_actors() {
+		let a = test_actor(0, 0, 1);
+		let b = test_actor(0, 0, 2);
+		let mut grid = SpatialGrid::new(64);
+		grid.rebuild(&[a.clone(), b.clone()]);
+
+		let found = grid.actors_in_rect(&rect(0, 0, 16, 16), Some(2));
+		assert_eq!(found.len(), 1);
+		assert!(Rc::ptr_eq(&found[0], &b));
+
+		let _borrowed = a.borrow_mut();
+		let found = grid.actors_in_rect(&rect(0, 0, 16, 16), None);
+		assert_eq!(found.len(), 1);
+		assert!(Rc::ptr_eq(&found[0], &b));
+	}
+
+	#[test]
+	fn update_moves_actor_to_new_cells() {
+		let actor = test_actor(0, 0, 0);
+		let mut grid = SpatialGrid::new(64);
+		grid.rebuild(slice::from_ref(&actor));
+
+		actor.borrow_mut().actor_info_mut().x = 500;
+		assert!(grid.actors_in_rect(&rect(500, 0, 16, 16), None).is_empty());
+
+		grid.update(&actor, actor.borrow().actor_info().world_collision_bounds());
+		assert_eq!(grid.actors_in_rect(&rect(500, 0, 16, 16), None).len(), 1);
+		assert!(grid.actors_in_rect(&rect(0, 0, 16, 16), None).is_empty());
+
+		grid.update(&actor, None);
+		assert!(grid.actors_in_rect(&rect(500, 0, 16, 16), None).is_empty());
+	}
+
+	#[test]
+	fn radius_query_uses_closest_point() {
+		let actor = test_actor(100, 100, 0);
+		let mut grid = SpatialGrid::new(64);
+		grid.rebuild(slice::from_ref(&actor));
+
+		// Closest point of the bounds is (100, 100), 5 pixels left and 0 pixels up of (95, 100)
+		assert_eq!(grid.actors_in_radius(95, 100, 5, None).len(), 1);
+		assert!(grid.actors_in_radius(95, 100, 4, None).is_empty());
+		// Diagonal distance to the corner is 5 for a 3-4-5 triangle
+		assert_eq!(grid.actors_in_radius(97, 96, 5, None).len(), 1);
+		assert!(grid.actors_in_radius(96, 96, 5, None).is_empty());
+	}
+
+	#[test]
+	fn raycast_finds_closest_actor() {
+		let near = test_actor(50, 0, 0);
+		let far = test_actor(150, 0, 0);
+		let mut grid = SpatialGrid::new(64);
+		grid.rebuild(&[far.clone(), near.clone()]);
+
+		let hit = grid.raycast(0, 8, 300, 8, None).unwrap();
+		assert!(Rc::ptr_eq(&hit.actor, &near));
+		assert_eq!((hit.x, hit.y), (50, 8));
+
+		let hit = grid.raycast(300, 8, 0, 8, None).unwrap();
+		assert!(Rc::ptr_eq(&hit.actor, &far));
+		assert_eq!((hit.x, hit.y), (165, 8));
+
+		assert!(grid.raycast(0, 40, 300, 40, None).is_none());
+	}
+
+	#[test]
+	fn long_rays_and_large_rects_find_actors() {
+		let near = test_actor(1000, 1000, 0);
+		let far = test_actor(-5000, 3000, 0);
+		let mut actors = vec![near.clone(), far.clone()];
+		// Fill a row of cells so that short rays walk the grid instead of checking every entry
+		for i in 0..40 {
+			actors.push(test_actor(i * 64, -1000, 0));
+		}
+		let mut grid = SpatialGrid::new(64);
+		grid.rebuild(&actors);
+
+		let hit = grid.raycast(0, 0, 1200, 1200, None).unwrap();
+		assert!(Rc::ptr_eq(&hit.actor, &near));
+		assert_eq!((hit.x, hit.y), (1000, 1000));
+		let hit = grid.raycast(1200, 1200, 0, 0, None).unwrap();
+		assert_eq!((hit.x, hit.y), (1015, 1015));
+		let hit = grid.raycast(-4990, 2000, -4995, 3500, None).unwrap();
+		assert!(Rc::ptr_eq(&hit.actor, &far));
+		assert_eq!((hit.x, hit.y), (-4993, 3000));
+
+		let hit = grid.raycast(0, 0, isize::MAX / 4, isize::MAX / 4, None).unwrap();
+		assert!(Rc::ptr_eq(&hit.actor, &near));
+		let huge = rect(-isize::MAX / 4, 0, isize::MAX / 2, isize::MAX / 4);
+		assert_eq!(grid.actors_in_rect(&huge, None).len(), 2);
+	}
+}
@@ -3,10 +3,12 @@ extern crate hex;
 
 use std::io;
 use std::rc::Rc;
+use std::cmp::max;
 use std::collections::HashMap;
 use asset;
 use asset::AssetNamespace;
 use tile::{PaletteWithOffset, Animation};
+use actor::BoundingRect;
 
 #[derive(Serialize, Deserialize)]
 struct RawSpriteTile {
@@ -15,12 +17,40 @@ struct RawSpriteTile {
 	pub data: String
 }
 
+#[derive(Serialize, Deserialize)]
+struct RawAnimationEvent {
+	frame: usize,
+	name: String
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawAnimationBox {
+	frame: usize,
+	kind: u32,
+	channel: u32,
+	x: isize,
+	y: isize,
+	w: isize,
+	h: isize
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawSpriteFrame {
+	width: usize,
+	height: usize,
+	origin_x: Option<isize>,
+	origin_y: Option<isize>
+}
+
 #[derive(Serialize, Deserialize)]
 struct RawSpriteAnimation {
 	name: String,
 	tile: RawSpriteTile,
 	anim: Vec<usize>,
-	looping: bool
+	looping: bool,
+	events: Option<Vec<RawAnimationEvent>>,
+	boxes: Option<Vec<RawAnimationBox>>,
+	frames: Option<Vec<RawSpriteFrame>>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -33,17 +63,55 @@ struct RawSprite {
 	anim: Vec<RawSpriteAnimation>
 }
 
+// Named event attached to a frame of an animation, sent to the actor when the frame starts
+#[derive(Clone, Debug)]
+pub struct AnimationEvent {
+	pub frame: usize,
+	pub name: String
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnimationBoxKind {
+	// Area that deals damage to other actors
+	Hitbox,
+	// Area that can receive damage from other actors
+	Hurtbox
+}
+
+// Typed rectangle attached to a frame of an animation, relative to the frame's origin. Boxes only
+// interact with boxes on the same channel.
+#[derive(Clone, Debug)]
+pub struct AnimationBox {
+	pub kind: AnimationBoxKind,
+	pub channel: u32,
+	pub bounds: BoundingRect
+}
+
+// Size and location of a frame's pixel data within its animation. The origin is the point in the
+// frame that is placed at the sprite's position, so that frames of different sizes stay aligned.
+#[derive(Clone, Debug)]
+pub struct SpriteFrame {
+	pub offset: usize,
+	pub size: usize,
+	pub width: usize,
+	pub height: usize,
+	pub origin_x: isize,
+	pub origin_y: isize
+}
+
 pub struct SpriteAnimation {
 	pub name: String,
 	pub width: usize,
 	pub height: usize,
 	pub depth: usize,
-	pub single_frame_size: usize,
 	pub palette: Option<PaletteWithOffset>,
 	pub data: Vec<u8>,
 	pub animation: Animation,
 	pub frames: usize,
-	pub looping: bool
+	pub looping: bool,
+	pub events: Vec<AnimationEvent>,
+	pub boxes: Vec<Vec<AnimationBox>>,
+	pub frame_layouts: Vec<SpriteFrame>
 }
 
 pub struct Sprite {
@@ -52,14 +120,40 @@ pub struct Sprite {
 	pub width: usize,
 	pub height: usize,
 	pub depth: usize,
-	pub single_frame_size: usize,
 	pub animations: Vec<Rc<SpriteAnimation>>,
 	pub animations_by_name: HashMap<String, Rc<SpriteAnimation>>
 }
 
+fn frame_data_size(width: usize, height: usize, depth: usize) -> usize {
+	match depth {
+		4 => ((width + 1) / 2) * height,
+		8 => width * height,
+		16 => width * height * 2,
+		_ => panic!("Invalid sprite depth {}", depth)
+	}
+}
+
+impl SpriteFrame {
+	// Layout for animations where every frame has the same size, with the origin at the top left
+	pub fn uniform(width: usize, height: usize, depth: usize, frames: usize) -> Vec<SpriteFrame> {
+		let size = frame_data_size(width, height, depth);
+		(0..frames).map(|frame| SpriteFrame {
+			offset: frame * size,
+			size, width, height,
+			origin_x: 0,
+			origin_y: 0
+		}).collect()
+	}
+}
+
 impl SpriteAnimation {
 	pub fn data_for_frame(&self, frame: usize) -> &[u8] {
-		&self.data[(frame * self.single_frame_size) .. ((frame + 1) * self.single_frame_size)]
+		let layout = &self.frame_layouts[frame];
+		&self.data[layout.offset .. layout.offset + layout.size]
+	}
+
+	pub fn frame_layout_for_time(&self, t: usize) -> &SpriteFrame {
+		&self.frame_layouts[self.frame_for_time(t)]
 	}
 
 	pub fn frame_for_time(&self, t: usize) -> usize {
@@ -75,22 +169,64 @@ impl SpriteAnimation {
 	pub fn data_for_time(&self, t: usize) -> &[u8] {
 		self.data_for_frame(self.frame_for_time(t))
 	}
+
+	// Frame that starts at time `t` when playing forward at normal speed, if any. Looping animations
+	// restart their first frame each loop.
+	pub fn frame_started_at(&self, t: usize) -> Option<usize> {
+		let t = if self.looping {
+			t % self.animation.total_length
+		} else if t >= self.animation.total_length {
+			return None;
+		} else {
+			t
+		};
+
+		let frame = self.animation.frame_for_time[t];
+		if (t == 0) || (self.animation.frame_for_time[t - 1] != frame) {
+			Some(frame)
+		} else {
+			None
+		}
+	}
+
+	pub fn events_for_frame(&self, frame: usize) -> Vec<&AnimationEvent> {
+		self.events.iter().filter(|event| event.frame == frame).collect()
+	}
+
+	pub fn boxes_for_frame(&self, frame: usize) -> &[AnimationBox] {
+		match self.boxes.get(frame) {
+			Some(boxes) => boxes,
+			None => &[]
+		}
+	}
+
+	pub fn boxes_for_time(&self, t: usize) -> &[AnimationBox] {
+		self.boxes_for_frame(self.frame_for_time(t))
+	}
+
+	// Non-looping animations finish when their last frame ends when playing forward at normal speed
+	pub fn is_finished_at(&self, t: usize) -> bool {
+		!self.looping && (t >= self.animation.total_length)
+	}
+}
+
+impl AnimationBoxKind {
+	fn from_raw(kind: u32) -> Result<AnimationBoxKind, io::Error> {
+		match kind {
+			0 => Ok(AnimationBoxKind::Hitbox),
+			1 => Ok(AnimationBoxKind::Hurtbox),
+			_ => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid animation box kind"))
+		}
+	}
 }
 
 impl Sprite {
 	pub fn new(name: &str, width: usize, height: usize, depth: usize) -> Sprite {
+		assert!((depth == 4) || (depth == 8) || (depth == 16), "Invalid sprite depth {}", depth);
 		Sprite {
 			name: name.to_string(),
 			id: asset::RUNTIME_ASSET.to_string(),
 			width, height, depth,
-			single_frame_size: {
-				match depth {
-					4 => ((width + 1) / 2) * height,
-					8 => width * height,
-					16 => width * height * 2,
-					_ => panic!("Invalid sprite depth {}", depth)
-				}
-			},
 			animations: Vec::new(),
 			animations_by_name: HashMap::new()
 		}
@@ -98,21 +234,17 @@ impl Sprite {
 
 	pub fn import(assets: &AssetNamespace, data: &str) -> Result<Rc<Sprite>, io::Error> {
 		let raw_sprite: RawSprite = serde_json::from_str(data)?;
+		match raw_sprite.depth {
+			4 | 8 | 16 => (),
+			_ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+				format!("Invalid sprite depth {}", raw_sprite.depth)))
+		};
 		let mut sprite = Sprite {
 			name: raw_sprite.name,
 			id: raw_sprite.id,
 			width: raw_sprite.width,
 			height: raw_sprite.height,
 			depth: raw_sprite.depth,
-			single_frame_size: {
-				match raw_sprite.depth {
-					4 => ((raw_sprite.width + 1) / 2) * raw_sprite.height,
-					8 => raw_sprite.width * raw_sprite.height,
-					16 => raw_sprite.width * raw_sprite.height * 2,
-					_ => return Err(io::Error::new(io::ErrorKind::InvalidData,
-						format!("Invalid sprite depth {}", raw_sprite.depth)))
-				}
-			},
 			animations: Vec::new(),
 			animations_by_name: HashMap::new()
 		};
@@ -156,21 +288,82 @@ impl Sprite {
 				Ok(decoded_data) => decoded_data,
 				Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "Sprite data is invalid"))
 			};
-			if data.len() != (frames * sprite.single_frame_size) {
+
+			// Frames without an explicit layout are all the size of the sprite. Frames with a layout are
+			// packed one after the other, each taking only the space needed for its own size.
+			let frame_layouts = match raw_sprite_anim.frames {
+				Some(raw_frames) => {
+					if raw_frames.len() != frames {
+						return Err(io::Error::new(io::ErrorKind::InvalidData,
+							"Frame layout count is incorrect for its animation"));
+					}
+					let mut offset = 0;
+					let mut layouts = Vec::new();
+					for raw_frame in raw_frames {
+						let size = frame_data_size(raw_frame.width, raw_frame.height, sprite.depth);
+						layouts.push(SpriteFrame {
+							offset, size,
+							width: raw_frame.width,
+							height: raw_frame.height,
+							origin_x: raw_frame.origin_x.unwrap_or(0),
+							origin_y: raw_frame.origin_y.unwrap_or(0)
+						});
+						offset += size;
+					}
+					layouts
+				},
+				None => SpriteFrame::uniform(sprite.width, sprite.height, sprite.depth, frames)
+			};
+			let data_size: usize = frame_layouts.iter().map(|layout| layout.size).sum();
+			if data.len() != data_size {
 				return Err(io::Error::new(io::ErrorKind::InvalidData, "Sprite data size is incorrect for its animation"));
 			}
 
+			let mut events = Vec::new();
+			if let Some(raw_events) = raw_sprite_anim.events {
+				for raw_event in raw_events {
+					if raw_event.frame >= frames {
+						return Err(io::Error::new(io::ErrorKind::InvalidData,
+							format!("Animation event {} is on an invalid frame", raw_event.name)));
+					}
+					events.push(AnimationEvent {
+						frame: raw_event.frame,
+						name: raw_event.name
+					});
+				}
+			}
+
+			let mut boxes = Vec::new();
+			if let Some(raw_boxes) = raw_sprite_anim.boxes {
+				boxes.resize(frames, Vec::new());
+				for raw_box in raw_boxes {
+					if raw_box.frame >= frames {
+						return Err(io::Error::new(io::ErrorKind::InvalidData, "Animation box is on an invalid frame"));
+					}
+					boxes[raw_box.frame].push(AnimationBox {
+						kind: AnimationBoxKind::from_raw(raw_box.kind)?,
+						channel: raw_box.channel,
+						bounds: BoundingRect {
+							x: raw_box.x,
+							y: raw_box.y,
+							width: raw_box.w,
+							height: raw_box.h
+						}
+					});
+				}
+			}
+
 			let mut sprite_anim = SpriteAnimation {
 				name: raw_sprite_anim.name,
-				width: sprite.width,
-				height: sprite.height,
+				width: frame_layouts.iter().fold(0, |width, layout| max(width, layout.width)),
+				height: frame_layouts.iter().fold(0, |height, layout| max(height, layout.height)),
 				depth: sprite.depth,
-				single_frame_size: sprite.single_frame_size,
 				palette,
 				data,
 				animation,
 				frames,
-				looping: raw_sprite_anim.looping
+				looping: raw_sprite_anim.looping,
+				events, boxes, frame_layouts
 			};
 
 			let sprite_anim_rc = Rc::new(sprite_anim);
@@ -181,10 +374,10 @@ impl Sprite {
 		Ok(Rc::new(sprite))
 	}
 
+	// Animations may have frames of any size, but must share the pixel depth of the sprite
 	pub fn push(&mut self, animation: SpriteAnimation) {
-		assert_eq!(self.width, animation.width);
-		assert_eq!(self.height, animation.height);
 		assert_eq!(self.depth, animation.depth);
+		assert_eq!(animation.frame_layouts.len(), animation.frames);
 		let animation_rc = Rc::new(animation);
 		self.animations.push(Rc::clone(&animation_rc));
 		self.animations_by_name.insert(animation_rc.name.clone(), animation_rc);
@@ -3,6 +3,7 @@ extern crate hex;
 
 use std::io;
 use std::rc::Rc;
+use std::cmp::{min, max};
 use std::collections::HashMap;
 use palette::Palette;
 use asset;
@@ -23,13 +24,36 @@ struct RawCollisionChannel {
 	pub bounds: Vec<RawBoundingRect>
 }
 
+#[derive(Serialize, Deserialize)]
+struct RawSlope {
+	pub x: usize,
+	pub y: usize,
+	pub w: usize,
+	pub h: usize,
+	pub left: usize,
+	pub right: usize
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawSlopeChannel {
+	pub channel: u32,
+	pub slopes: Vec<RawSlope>
+}
+
 #[derive(Serialize, Deserialize)]
 struct RawTile {
 	pub palette: Option<String>,
 	pub offset: Option<usize>,
 	pub data: String,
 	pub collision: Option<Vec<RawBoundingRect>>,
-	pub collision_channels: Option<Vec<RawCollisionChannel>>
+	pub collision_channels: Option<Vec<RawCollisionChannel>>,
+	pub anim: Option<Vec<usize>>,
+	pub anim_mode: Option<u32>,
+	pub slopes: Option<Vec<RawSlope>>,
+	pub one_way: Option<Vec<RawBoundingRect>>,
+	pub slope_channels: Option<Vec<RawSlopeChannel>>,
+	pub one_way_channels: Option<Vec<RawCollisionChannel>>,
+	pub properties: Option<HashMap<String, serde_json::Value>>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -49,11 +73,40 @@ pub struct PaletteWithOffset {
 	pub offset: usize
 }
 
+// Floor slope within a tile. The area below the line between the surface heights at the left and right
+// edges of the bounds is solid. Heights are measured up from the bottom of the bounds.
+#[derive(Clone, Debug)]
+pub struct Slope {
+	pub bounds: BoundingRect,
+	pub left_height: isize,
+	pub right_height: isize
+}
+
 pub struct Tile {
 	pub palette: Option<PaletteWithOffset>,
 	pub data: Vec<u8>,
 	pub collision: Vec<BoundingRect>,
-	pub collision_channels: HashMap<u32, Vec<BoundingRect>>
+	pub collision_channels: HashMap<u32, Vec<BoundingRect>>,
+	pub slopes: Vec<Slope>,
+	// Solids that only block from above, so that actors can jump up through them
+	pub one_way: Vec<BoundingRect>,
+	// Slopes and one-way solids that only apply to actors and rays on a given collision channel, in
+	// addition to the ones above that apply to every channel
+	pub slope_channels: HashMap<u32, Vec<Slope>>,
+	pub one_way_channels: HashMap<u32, Vec<BoundingRect>>,
+	// Custom properties such as terrain type or friction, for actors to react to
+	pub properties: HashMap<String, serde_json::Value>,
+	pub animation: Option<Animation>,
+	pub animation_mode: TileAnimationMode
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TileAnimationMode {
+	Loop,
+	// Plays through once, then holds the last frame
+	Once,
+	// Holds the first frame until triggered at a position in the layer, then plays through once
+	Trigger
 }
 
 pub struct Animation {
@@ -74,7 +127,86 @@ pub struct TileSet {
 	pub animation: Option<Animation>
 }
 
+impl TileAnimationMode {
+	fn from_raw(mode: u32) -> Result<TileAnimationMode, io::Error> {
+		match mode {
+			0 => Ok(TileAnimationMode::Loop),
+			1 => Ok(TileAnimationMode::Once),
+			2 => Ok(TileAnimationMode::Trigger),
+			_ => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid tile animation mode"))
+		}
+	}
+}
+
+impl Tile {
+	pub fn get_property(&self, name: &str) -> Option<&serde_json::Value> {
+		self.properties.get(name)
+	}
+
+	pub fn slopes_for_channel<'a>(&'a self, channel: u32) -> impl Iterator<Item = &'a Slope> + 'a {
+		self.slopes.iter().chain(self.slope_channels.get(&channel).into_iter().flatten())
+	}
+
+	pub fn one_way_for_channel<'a>(&'a self, channel: u32) -> impl Iterator<Item = &'a BoundingRect> + 'a {
+		self.one_way.iter().chain(self.one_way_channels.get(&channel).into_iter().flatten())
+	}
+}
+
+impl Slope {
+	fn from_raw(slope: RawSlope) -> Result<Slope, io::Error> {
+		if (slope.left > slope.h) || (slope.right > slope.h) {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "Slope height is outside its bounds"));
+		}
+		Ok(Slope {
+			bounds: BoundingRect {
+				x: slope.x as isize,
+				y: slope.y as isize,
+				width: slope.w as isize,
+				height: slope.h as isize
+			},
+			left_height: slope.left as isize,
+			right_height: slope.right as isize
+		})
+	}
+
+	fn height_at(&self, offset: isize) -> isize {
+		self.left_height + ((self.right_height - self.left_height) * offset) / self.bounds.width
+	}
+
+	// Highest point of the surface between `left` and `right` in map coordinates, for a slope in a tile
+	// at (`x`, `y`). Returns None if the range does not overlap the slope.
+	pub fn highest_surface(&self, x: isize, y: isize, left: isize, right: isize) -> Option<isize> {
+		let slope_left = x + self.bounds.x;
+		let slope_right = slope_left + self.bounds.width;
+		if (right <= slope_left) || (left >= slope_right) || (self.bounds.width <= 0) {
+			return None;
+		}
+
+		// Surface is a line, so the highest point is at one end of the overlapping range
+		let start = max(left, slope_left) - slope_left;
+		let end = min(right, slope_right) - slope_left;
+		let height = max(self.height_at(start), self.height_at(end));
+		Some(y + self.bounds.y + self.bounds.height - height)
+	}
+}
+
 impl Animation {
+	fn validate_raw(frame_lengths: &[usize]) -> Result<(), io::Error> {
+		// Check animation length for sanity
+		let mut total_length = 0;
+		for frame_length in frame_lengths {
+			total_length += frame_length;
+		}
+
+		if total_length == 0 {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "Animation with zero length"));
+		}
+		if total_length >= 0x10000 {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "Animation too long"));
+		}
+		Ok(())
+	}
+
 	pub fn new(frame_lengths: Vec<usize>) -> Animation {
 		let mut total_length = 0;
 		for frame_length in &frame_lengths {
@@ -140,19 +272,7 @@ impl TileSet {
 
 		// Process animation data
 		if let Some(raw_animation) = raw_tile_set.anim {
-			// Check animation length for sanity
-			let mut total_length = 0;
-			for frame_length in &raw_animation {
-				total_length += frame_length;
-			}
-
-			if total_length == 0 {
-				return Err(io::Error::new(io::ErrorKind::InvalidData, "Animation with zero length"));
-			}
-			if total_length >= 0x10000 {
-				return Err(io::Error::new(io::ErrorKind::InvalidData, "Animation too long"));
-			}
-
+			Animation::validate_raw(&raw_animation)?;
 			let animation = Animation::new(raw_animation);
 			tile_set.frames = animation.frame_lengths.len();
 			tile_set.animation = Some(animation);
@@ -182,9 +302,26 @@ impl TileSet {
 				Ok(decoded_data) => decoded_data,
 				Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "Tile data is invalid"))
 			};
-			if data.len() != (tile_set.frames * tile_set.single_frame_size) {
+
+			// Tiles with their own animation have their own frame count, independent of the tile set
+			let animation = match raw_tile.anim {
+				Some(raw_animation) => {
+					Animation::validate_raw(&raw_animation)?;
+					Some(Animation::new(raw_animation))
+				},
+				None => None
+			};
+			let frames = match &animation {
+				Some(animation) => animation.frame_lengths.len(),
+				None => tile_set.frames
+			};
+			if data.len() != (frames * tile_set.single_frame_size) {
 				return Err(io::Error::new(io::ErrorKind::InvalidData, "Tile data size is incorrect for its tile set"));
 			}
+			let animation_mode = match raw_tile.anim_mode {
+				Some(mode) => TileAnimationMode::from_raw(mode)?,
+				None => TileAnimationMode::Loop
+			};
 
 			// Process collision data
 			let mut collision = Vec::new();
@@ -215,17 +352,82 @@ impl TileSet {
 				}
 			}
 
-			tile_set.tiles.push(Tile { palette, data, collision, collision_channels });
+			let mut slopes = Vec::new();
+			if let Some(raw_slopes) = raw_tile.slopes {
+				for slope in raw_slopes {
+					slopes.push(Slope::from_raw(slope)?);
+				}
+			}
+
+			let mut one_way = Vec::new();
+			if let Some(raw_one_way) = raw_tile.one_way {
+				for rect in raw_one_way {
+					one_way.push(BoundingRect {
+						x: rect.x as isize,
+						y: rect.y as isize,
+						width: rect.w as isize,
+						height: rect.h as isize
+					});
+				}
+			}
+
+			let mut slope_channels = HashMap::new();
+			if let Some(raw_slope_channels) = raw_tile.slope_channels {
+				for channel in raw_slope_channels {
+					let mut channel_slopes = Vec::new();
+					for slope in channel.slopes {
+						channel_slopes.push(Slope::from_raw(slope)?);
+					}
+					slope_channels.insert(channel.channel, channel_slopes);
+				}
+			}
+
+			let mut one_way_channels = HashMap::new();
+			if let Some(raw_one_way_channels) = raw_tile.one_way_channels {
+				for channel in raw_one_way_channels {
+					let mut bounds = Vec::new();
+					for rect in channel.bounds {
+						bounds.push(BoundingRect {
+							x: rect.x as isize,
+							y: rect.y as isize,
+							width: rect.w as isize,
+							height: rect.h as isize
+						});
+					}
+					one_way_channels.insert(channel.channel, bounds);
+				}
+			}
+
+			let properties = raw_tile.properties.unwrap_or_default();
+
+			tile_set.tiles.push(Tile { palette, data, collision, collision_channels, slopes, one_way,
+				slope_channels, one_way_channels, properties, animation, animation_mode });
 		}
 
 		Ok(Rc::new(tile_set))
 	}
 
 	pub fn push(&mut self, tile: Tile) {
-		assert!(tile.data.len() == (self.frames * self.single_frame_size), "Tile data size is incorrect for its tile set");
+		let frames = match &tile.animation {
+			Some(animation) => animation.frame_lengths.len(),
+			None => self.frames
+		};
+		assert!(tile.data.len() == (frames * self.single_frame_size), "Tile data size is incorrect for its tile set");
 		self.tiles.push(tile);
 	}
 
+	// Animation used by a tile, either its own or the one shared by the tile set
+	pub fn tile_animation(&self, tile: usize) -> Option<&Animation> {
+		match &self.tiles[tile].animation {
+			Some(animation) => Some(animation),
+			None => self.animation.as_ref()
+		}
+	}
+
+	pub fn is_animated(&self) -> bool {
+		self.animation.is_some() || self.tiles.iter().any(|tile| tile.animation.is_some())
+	}
+
 	pub fn data_for_frame(&self, tile: usize, frame: usize) -> &[u8] {
 		&self.tiles[tile].data[(frame * self.single_frame_size) .. ((frame + 1) * self.single_frame_size)]
 	}
@@ -237,7 +439,48 @@ impl TileSet {
 		}
 	}
 
+	// Frame of a tile `t` frames into its animation. Triggered tiles return to their first frame once
+	// they have played through.
+	pub fn tile_frame_for_time(&self, tile: usize, t: usize) -> usize {
+		match self.tile_animation(tile) {
+			Some(animation) => match self.tiles[tile].animation_mode {
+				TileAnimationMode::Loop => animation.frame_for_time[t % animation.total_length],
+				TileAnimationMode::Once => animation.frame_for_time[min(t, animation.total_length - 1)],
+				TileAnimationMode::Trigger => {
+					if t < animation.total_length {
+						animation.frame_for_time[t]
+					} else {
+						0
+					}
+				}
+			},
+			None => 0
+		}
+	}
+
 	pub fn data_for_time(&self, tile: usize, t: usize) -> &[u8] {
-		self.data_for_frame(tile, self.frame_for_time(t))
+		self.data_for_frame(tile, self.tile_frame_for_time(tile, t))
+	}
+}
+
+#[cfg(test)]
+impl TileSet {
+	// Tile set with a single blank tile that is solid over its whole area
+	pub fn solid_test_tile_set(width: usize, height: usize) -> TileSet {
+		let mut tile_set = TileSet::new("test", width, height, 8, None);
+		tile_set.push(Tile {
+			palette: None,
+			data: vec![0; width * height],
+			collision: vec![BoundingRect { x: 0, y: 0, width: width as isize, height: height as isize }],
+			collision_channels: HashMap::new(),
+			slopes: Vec::new(),
+			one_way: Vec::new(),
+			slope_channels: HashMap::new(),
+			one_way_channels: HashMap::new(),
+			properties: HashMap::new(),
+			animation: None,
+			animation_mode: TileAnimationMode::Loop
+		});
+		tile_set
 	}
 }
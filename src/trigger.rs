@@ -0,0 +1,82 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use actor::{ActorRef, BoundingRect};
+use map::MapActor;
+
+// Rectangular region of the map that reports actors entering, staying inside and leaving it. Zones
+// are checked once per frame after actors tick, against the collision bounds of actors whose
+// collision layer is in the zone's mask.
+pub struct TriggerZone {
+	pub name: String,
+	pub bounds: BoundingRect,
+	pub data: serde_json::Value,
+	pub collision_mask: u32,
+	pub enabled: bool,
+	occupants: Vec<ActorRef>
+}
+
+pub type TriggerZoneRef = Rc<RefCell<TriggerZone>>;
+
+// Actors whose overlap with a zone changed or continued during a frame
+pub struct TriggerZoneChanges {
+	pub entered: Vec<ActorRef>,
+	pub exited: Vec<ActorRef>,
+	pub stayed: Vec<ActorRef>
+}
+
+impl TriggerZone {
+	pub fn new(name: &str, bounds: BoundingRect) -> TriggerZone {
+		TriggerZone {
+			name: name.to_string(),
+			bounds,
+			data: serde_json::Value::Null,
+			collision_mask: 0xffffffff,
+			enabled: true,
+			occupants: Vec::new()
+		}
+	}
+
+	// Creates a zone covering the area of a map actor, with the actor's type as its name
+	pub fn from_map_actor(actor: &MapActor) -> TriggerZone {
+		let mut zone = TriggerZone::new(&actor.actor_type, BoundingRect {
+			x: actor.x,
+			y: actor.y,
+			width: actor.width,
+			height: actor.height
+		});
+		zone.data = actor.data.clone();
+		zone
+	}
+
+	pub fn occupants(&self) -> &[ActorRef] {
+		&self.occupants
+	}
+
+	pub fn contains(&self, actor: &ActorRef) -> bool {
+		self.occupants.iter().any(|occupant| Rc::ptr_eq(occupant, actor))
+	}
+
+	// Replaces the set of actors inside the zone with `inside`, returning how it changed since the
+	// last update
+	pub fn update(&mut self, inside: Vec<ActorRef>) -> TriggerZoneChanges {
+		let mut changes = TriggerZoneChanges {
+			entered: Vec::new(),
+			exited: Vec::new(),
+			stayed: Vec::new()
+		};
+		for actor in &inside {
+			if self.contains(actor) {
+				changes.stayed.push(actor.clone());
+			} else {
+				changes.entered.push(actor.clone());
+			}
+		}
+		for occupant in &self.occupants {
+			if !inside.iter().any(|actor| Rc::ptr_eq(actor, occupant)) {
+				changes.exited.push(occupant.clone());
+			}
+		}
+		self.occupants = inside;
+		changes
+	}
+}
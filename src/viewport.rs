@@ -0,0 +1,119 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use actor::BoundingRect;
+use camera::Camera;
+use render::{RenderSize, LayerCache};
+use ui::UILayoutRef;
+
+// Region of the screen showing its own view of the shared map and actors. The screen rectangle is
+// given in fractions of the render size, so that viewports follow changes in resolution.
+#[derive(Clone)]
+pub struct Viewport {
+	pub left: f32,
+	pub top: f32,
+	pub right: f32,
+	pub bottom: f32,
+	pub camera: Option<Camera>,
+	pub camera_shake_x: isize,
+	pub camera_shake_y: isize,
+	pub scroll_x: isize,
+	pub scroll_y: isize,
+	pub ui_layouts: Vec<UILayoutRef>,
+	pub layer_caches: Vec<LayerCache>
+}
+
+pub type ViewportRef = Rc<RefCell<Viewport>>;
+
+impl Viewport {
+	pub fn new(left: f32, top: f32, right: f32, bottom: f32) -> Viewport {
+		Viewport {
+			left, top, right, bottom,
+			camera: None,
+			camera_shake_x: 0,
+			camera_shake_y: 0,
+			scroll_x: 0,
+			scroll_y: 0,
+			ui_layouts: Vec::new(),
+			layer_caches: Vec::new()
+		}
+	}
+
+	// Viewport for one of `count` side by side columns of the screen
+	pub fn column(index: usize, count: usize) -> Viewport {
+		Viewport::new(index as f32 / count as f32, 0.0, (index + 1) as f32 / count as f32, 1.0)
+	}
+
+	// Viewport for one of `count` stacked rows of the screen
+	pub fn row(index: usize, count: usize) -> Viewport {
+		Viewport::new(0.0, index as f32 / count as f32, 1.0, (index + 1) as f32 / count as f32)
+	}
+
+	pub fn screen_rect(&self, render_size: &RenderSize) -> BoundingRect {
+		let left = (render_size.width as f32 * self.left.clamp(0.0, 1.0)) as isize;
+		let top = (render_size.height as f32 * self.top.clamp(0.0, 1.0)) as isize;
+		let right = (render_size.width as f32 * self.right.clamp(0.0, 1.0)) as isize;
+		let bottom = (render_size.height as f32 * self.bottom.clamp(0.0, 1.0)) as isize;
+		BoundingRect {
+			x: left,
+			y: top,
+			width: (right - left).max(0),
+			height: (bottom - top).max(0)
+		}
+	}
+
+	pub fn render_size(&self, render_size: &RenderSize) -> RenderSize {
+		let rect = self.screen_rect(render_size);
+		RenderSize {
+			width: rect.width as usize,
+			height: rect.height as usize
+		}
+	}
+
+	pub fn set_camera(&mut self, camera: Option<Camera>) {
+		self.camera = camera;
+	}
+
+	pub fn set_camera_shake(&mut self, x: isize, y: isize) {
+		self.camera_shake_x = x;
+		self.camera_shake_y = y;
+	}
+
+	pub fn set_scroll(&mut self, x: isize, y: isize) {
+		self.scroll_x = x;
+		self.scroll_y = y;
+	}
+
+	pub fn add_ui_layout(&mut self, layout: UILayoutRef) {
+		self.ui_layouts.push(layout);
+	}
+
+	pub fn remove_ui_layout(&mut self, layout: &UILayoutRef) {
+		self.ui_layouts.retain(|check| !Rc::ptr_eq(check, layout));
+	}
+
+	pub fn invalidate_layer_caches(&mut self) {
+		for cache in &mut self.layer_caches {
+			cache.invalidate();
+		}
+	}
+
+	pub fn set_map_bounds(&mut self, bounds: Option<BoundingRect>) {
+		if let Some(camera) = &mut self.camera {
+			if let Some(bounds) = bounds {
+				camera.map_bounds = bounds;
+			}
+			camera.force_snap = true;
+		}
+		self.camera_shake_x = 0;
+		self.camera_shake_y = 0;
+	}
+
+	pub fn tick(&mut self, render_size: &RenderSize) {
+		let viewport_size = self.render_size(render_size);
+		if let Some(camera) = &mut self.camera {
+			camera.tick(&viewport_size, &mut self.scroll_x, &mut self.scroll_y);
+			self.scroll_x += self.camera_shake_x;
+			self.scroll_y += self.camera_shake_y;
+		}
+	}
+}